@@ -80,6 +80,17 @@ local function on_exit(_, code, _)
   end
 end
 
+-- How the bridge launches the CLI, as `configure` and `doctor --config` take it.
+function M.launch_config(opts)
+  return {
+    claude_path = opts.claude_path,
+    -- An empty table would encode as a JSON array.
+    env = opts.env and next(opts.env) and opts.env or nil,
+    env_passthrough = opts.env_passthrough,
+    profiles = opts.profiles and next(opts.profiles) and opts.profiles or nil,
+  }
+end
+
 function M.start(opts)
   if job_id then
     return true
@@ -103,6 +114,13 @@ function M.start(opts)
     return false
   end
 
+  if opts then
+    local params = M.launch_config(opts)
+    params.shadow_commits = opts.shadow_commits
+    params.track_changes = opts.track_changes
+    M.send({ method = "configure", params = params })
+  end
+
   return true
 end
 
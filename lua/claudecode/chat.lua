@@ -7,6 +7,7 @@ local ns = vim.api.nvim_create_namespace("claudecode_chat")
 local streaming = false
 local stream_line = nil
 local current_session_id = nil
+local last_changeset = nil
 
 local function get_buf()
   return ui.get_chat_buf()
@@ -103,8 +104,51 @@ local function on_event(data)
     end
     append_to_chat({ "", ">> " .. summary })
 
-    if tool == "Edit" and data.input then
-      require("claudecode.diff").show(data.id, data.input)
+
+  elseif evt == "proposed_change" then
+    require("claudecode.diff").show_change(data)
+
+  elseif evt == "worktree" then
+    if data.state == "created" or data.state == "restored" then
+      append_to_chat({ "", "[Working in " .. data.path .. " on branch " .. data.branch .. "]" })
+    else
+      -- The session's files lived in the worktree; start afresh.
+      current_session_id = nil
+      append_to_chat({ "", "[Worktree " .. data.state .. ": " .. data.branch .. "]", "" })
+      if data.state == "merged" then
+        vim.cmd("checktime")
+      end
+    end
+
+  elseif evt == "merge_result" then
+    require("claudecode.diff").show_merge(data)
+
+  elseif evt == "conflict" then
+    local why = data.reason == "unsaved" and "has unsaved changes" or "changed after Claude read it"
+    local name = vim.fn.fnamemodify(data.path or "?", ":~:.")
+    append_to_chat({ "   [conflict] " .. name .. " " .. why })
+    vim.notify("[claudecode] Claude is editing " .. name .. ", which " .. why, vim.log.levels.WARN)
+
+  elseif evt == "unresolved_mentions" then
+    local mentions = table.concat(data.mentions or {}, ", ")
+    append_to_chat({ "   [unresolved] " .. mentions })
+    vim.notify("[claudecode] No files match " .. mentions, vim.log.levels.WARN)
+
+  elseif evt == "file_changed" then
+    local b = data.path and vim.fn.bufnr(data.path) or -1
+    if b ~= -1 and vim.api.nvim_buf_is_loaded(b) then
+      vim.cmd("checktime " .. b)
+    end
+
+  elseif evt == "changeset" then
+    if #(data.files or {}) > 0 then
+      last_changeset = data
+      local lines = { "", string.format("[Changed %d files, +%d -%d] (:ClaudeChanges for the diff)", #data.files, data.added or 0, data.deleted or 0) }
+      for _, f in ipairs(data.files) do
+        table.insert(lines, string.format("   %s %s +%d -%d", f.kind:sub(1, 1):upper(), f.path, f.added or 0, f.deleted or 0))
+      end
+      append_to_chat(lines)
+      vim.cmd("checktime")
     end
 
   elseif evt == "tool_result" then
@@ -137,9 +181,184 @@ local function on_event(data)
     append_to_chat({ "", "---", "" })
 
   elseif evt == "error" then
+    if data.fatal ~= false then
+      streaming = false
+      stream_line = nil
+    end
+    local label = data.code and ("[ERROR " .. data.code .. "] ") or "[ERROR] "
+    append_to_chat({ "", label .. (data.message or "Unknown error"), "" })
+
+  elseif evt == "retrying" then
     streaming = false
     stream_line = nil
-    append_to_chat({ "", "[ERROR] " .. (data.message or "Unknown error"), "" })
+    append_to_chat({
+      "",
+      string.format("[Retrying, attempt %d in %.1fs] %s", data.attempt or 0, (data.delay_ms or 0) / 1000, data.reason or ""),
+    })
+
+  elseif evt == "steer_ack" then
+    local state = data.delivered and "sent" or "held until the current step finishes"
+    vim.notify("[claudecode] Steering message " .. state, vim.log.levels.INFO)
+
+  elseif evt == "stalled" then
+    vim.notify(string.format("[claudecode] No output from Claude for %ds", math.floor((data.idle_ms or 0) / 1000)), vim.log.levels.WARN)
+
+  elseif evt == "status" then
+    local lines = {
+      "session: " .. (data.session_id or "none") .. (data.active and " (active)" or ""),
+      "model: " .. (data.model or "default") .. (data.profile and (" | profile: " .. data.profile) or ""),
+      "cwd: " .. (data.cwd or "?"),
+      string.format("queued: %d | session cost: $%.4f", data.queue_length or 0, data.total_cost_usd or 0),
+    }
+    if data.worktree then
+      table.insert(lines, "worktree: " .. data.worktree)
+    end
+    if data.elapsed_ms then
+      table.insert(lines, string.format("turn: %ds, %d tokens, %d tool calls%s (pid %s)",
+        math.floor(data.elapsed_ms / 1000),
+        data.output_tokens or 0,
+        data.tool_calls or 0,
+        data.current_tool and (", running " .. data.current_tool) or "",
+        data.pid or "?"
+      ))
+    end
+    vim.notify("[claudecode]\n" .. table.concat(lines, "\n"), vim.log.levels.INFO)
+
+  elseif evt == "doctor" then
+    local lines = {}
+    local level = vim.log.levels.INFO
+    for _, item in ipairs(data.checks or {}) do
+      table.insert(lines, "[" .. item.status .. "] " .. item.name .. ": " .. item.message)
+      if item.status == "fail" then
+        level = vim.log.levels.ERROR
+      elseif item.status == "warn" and level ~= vim.log.levels.ERROR then
+        level = vim.log.levels.WARN
+      end
+    end
+    vim.notify("[claudecode] Doctor\n" .. table.concat(lines, "\n"), level)
+
+  elseif evt == "checkpoints" then
+    if #(data.checkpoints or {}) == 0 then
+      vim.notify("[claudecode] No checkpoints for this session", vim.log.levels.INFO)
+      return
+    end
+    vim.ui.select(data.checkpoints, {
+      prompt = "Roll back to before turn:",
+      format_item = function(cp)
+        return string.format("%d  %s  (%d files)  %s", cp.turn, os.date("%H:%M", cp.created_at), #cp.files, cp.prompt:gsub("\n", " "))
+      end,
+    }, function(choice)
+      if choice then
+        M.rollback(choice.turn, false)
+      end
+    end)
+
+  elseif evt == "shadow_log" then
+    if #(data.entries or {}) == 0 then
+      vim.notify("[claudecode] No shadow commits in this repository", vim.log.levels.INFO)
+      return
+    end
+    local entries = data.entries
+    table.sort(entries, function(a, b)
+      return a.created_at > b.created_at
+    end)
+    vim.ui.select(entries, {
+      prompt = "Shadow commit:",
+      format_item = function(e)
+        return string.format("%s  %s  %s#%d  $%.4f  %s", e.commit:sub(1, 8), os.date("%Y-%m-%d %H:%M", e.created_at), e.session_id:sub(1, 8), e.turn, e.cost_usd, e.prompt:gsub("\n", " "))
+      end,
+    }, function(choice)
+      if not choice then
+        return
+      end
+      local out = vim.fn.systemlist({ "git", "show", "--stat", "--patch", choice.commit })
+      vim.cmd("tabnew")
+      local b = vim.api.nvim_get_current_buf()
+      vim.bo[b].buftype = "nofile"
+      vim.bo[b].bufhidden = "wipe"
+      vim.api.nvim_buf_set_lines(b, 0, -1, false, out)
+      vim.bo[b].modifiable = false
+      vim.bo[b].filetype = "git"
+    end)
+
+  elseif evt == "rollback_result" then
+    local msg = string.format("[claudecode] Rolled back to before turn %d: %d files restored", data.turn, #(data.restored or {}))
+    if #(data.conflicts or {}) > 0 then
+      msg = msg .. "\nChanged since the turn, left alone (use :ClaudeRollback! " .. data.turn .. " to overwrite):\n  "
+        .. table.concat(data.conflicts, "\n  ")
+      vim.notify(msg, vim.log.levels.WARN)
+    else
+      vim.notify(msg, vim.log.levels.INFO)
+    end
+    vim.cmd("checktime")
+
+  elseif evt == "pong" then
+    vim.notify(string.format("[claudecode] Bridge %s up for %ds", data.version or "?", math.floor((data.uptime_ms or 0) / 1000)), vim.log.levels.INFO)
+
+  elseif evt == "queued" then
+    vim.notify("[claudecode] Prompt queued at position " .. (data.position or "?"), vim.log.levels.INFO)
+
+  elseif evt == "queue_started" then
+    append_to_chat({ "", "[Starting queued prompt]" })
+
+  elseif evt == "model_fallback" then
+    append_to_chat({ "", "[Model fallback: " .. (data.from or "default") .. " -> " .. (data.to or "?") .. "]" })
+
+  elseif evt == "usage_limit" then
+    local reset = data.reset_at and os.date("%H:%M", data.reset_at) or "an unknown time"
+    if data.limited then
+      vim.notify("[claudecode] Usage limit reached, resets at " .. reset, vim.log.levels.WARN)
+    elseif data.reset_at and data.reset_at > os.time() then
+      vim.notify("[claudecode] Approaching usage limit, resets at " .. reset, vim.log.levels.WARN)
+    else
+      vim.notify("[claudecode] Usage limit window has reset", vim.log.levels.INFO)
+    end
+
+  elseif evt == "log" then
+    if data.severity == "warning" then
+      vim.notify("[claudecode] " .. (data.message or ""), vim.log.levels.WARN)
+    end
+  end
+end
+
+-- Hash of the buffer as it would be written, matching the bridge's hash of
+-- the file on disk.
+local function buffer_hash(buf)
+  local lines = vim.api.nvim_buf_get_lines(buf, 0, -1, false)
+  if #lines == 1 and lines[1] == "" then
+    return vim.fn.sha256("")
+  end
+  local nl = vim.bo[buf].fileformat == "dos" and "\r\n" or "\n"
+  local text = table.concat(lines, nl)
+  if vim.bo[buf].eol or vim.bo[buf].fixeol then
+    text = text .. nl
+  end
+  return vim.fn.sha256(text)
+end
+
+function M.push_buffer_state(buf, closed)
+  if not bridge.is_running() or vim.bo[buf].buftype ~= "" then
+    return
+  end
+  local path = vim.api.nvim_buf_get_name(buf)
+  if path == "" then
+    return
+  end
+  bridge.send({
+    method = "buffer_state",
+    params = {
+      path = path,
+      content_hash = not closed and buffer_hash(buf) or nil,
+      modified = not closed and vim.bo[buf].modified,
+    },
+  })
+end
+
+local function push_all_buffer_states()
+  for _, buf in ipairs(vim.api.nvim_list_bufs()) do
+    if vim.api.nvim_buf_is_loaded(buf) then
+      M.push_buffer_state(buf)
+    end
   end
 end
 
@@ -152,6 +371,7 @@ function M.send(prompt, context)
     if not bridge.start(require("claudecode").config) then
       return
     end
+    push_all_buffer_states()
   end
 
   append_to_chat({ "", "> " .. prompt:gsub("\n", "\n> "), "" })
@@ -159,7 +379,14 @@ function M.send(prompt, context)
   if current_session_id then
     bridge.send({
       method = "continue",
-      params = { prompt = prompt, context = context },
+      params = {
+        prompt = prompt,
+        context = context,
+        context_providers = require("claudecode").config.context_providers,
+        model = require("claudecode").config.model,
+        model_fallbacks = require("claudecode").config.model_fallbacks,
+        limits = require("claudecode").config.limits,
+      },
     })
   else
     bridge.send({
@@ -168,10 +395,15 @@ function M.send(prompt, context)
         prompt = prompt,
         cwd = vim.fn.getcwd(),
         context = context,
+        context_providers = require("claudecode").config.context_providers,
         model = require("claudecode").config.model,
+        model_fallbacks = require("claudecode").config.model_fallbacks,
         allowed_tools = require("claudecode").config.allowed_tools,
         append_system_prompt = require("claudecode").config.append_system_prompt,
         permission_mode = require("claudecode").config.permission_mode,
+        limits = require("claudecode").config.limits,
+        profile = require("claudecode").config.profile,
+        isolation = require("claudecode").config.isolation,
       },
     })
   end
@@ -182,6 +414,7 @@ function M.resume(session_id)
     if not bridge.start(require("claudecode").config) then
       return
     end
+    push_all_buffer_states()
   end
 
   append_to_chat({ "[Resuming session " .. session_id .. "]" })
@@ -203,10 +436,77 @@ function M.continue_chat(prompt)
 
   bridge.send({
     method = "continue",
-    params = { prompt = prompt },
+    params = {
+      prompt = prompt,
+      model = require("claudecode").config.model,
+      model_fallbacks = require("claudecode").config.model_fallbacks,
+      limits = require("claudecode").config.limits,
+    },
+  })
+end
+
+function M.steer(text)
+  if not bridge.is_running() then
+    vim.notify("[claudecode] No active session", vim.log.levels.WARN)
+    return
+  end
+
+  append_to_chat({ "", ">> (steer) " .. text:gsub("\n", "\n> "), "" })
+  bridge.send({
+    method = "steer",
+    params = { text = text },
+  })
+end
+
+function M.rollback(turn, force)
+  if not bridge.is_running() then
+    vim.notify("[claudecode] No active session", vim.log.levels.WARN)
+    return
+  end
+
+  if turn then
+    bridge.send({ method = "rollback", params = { turn = turn, force = force or false } })
+  else
+    bridge.send({
+      method = "list_checkpoints",
+      params = current_session_id and { session_id = current_session_id } or vim.empty_dict(),
+    })
+  end
+end
+
+function M.delete_checkpoints()
+  if not bridge.is_running() then
+    vim.notify("[claudecode] No active session", vim.log.levels.WARN)
+    return
+  end
+  bridge.send({
+    method = "delete_checkpoints",
+    params = current_session_id and { session_id = current_session_id } or vim.empty_dict(),
   })
 end
 
+function M.show_changes()
+  if not last_changeset then
+    if require("claudecode").config.track_changes then
+      vim.notify("[claudecode] No file changes from the last turn", vim.log.levels.INFO)
+    else
+      vim.notify("[claudecode] Set track_changes = true to record each turn's changes", vim.log.levels.INFO)
+    end
+    return
+  end
+  vim.cmd("tabnew")
+  local b = vim.api.nvim_get_current_buf()
+  vim.bo[b].buftype = "nofile"
+  vim.bo[b].bufhidden = "wipe"
+  local lines = vim.split(last_changeset.diff or "", "\n", { trimempty = true })
+  if last_changeset.truncated then
+    table.insert(lines, "# (diff truncated)")
+  end
+  vim.api.nvim_buf_set_lines(b, 0, -1, false, lines)
+  vim.bo[b].modifiable = false
+  vim.bo[b].filetype = "diff"
+end
+
 function M.new_session()
   current_session_id = nil
   append_to_chat({ "", "=== New Session ===", "" })
@@ -127,16 +127,63 @@ local function remove_buffer_keymaps(tool_use_id)
   pcall(vim.keymap.del, "n", config.keymaps.reject_diff, { buffer = diff.bufnr })
 end
 
-function M.show(tool_use_id, input)
-  if not input or not input.file_path then
+-- Lines and hash of the file as it is on disk.
+local function read_file(file_path)
+  local lines = vim.fn.readfile(file_path)
+  local hash = vim.fn.sha256(table.concat(vim.fn.readfile(file_path, "b"), "\n"))
+  return lines, hash
+end
+
+-- Rebuilds the proposed file from the bridge's hunks. Returns nil and the
+-- failing hunk's line when a context or removed line is not in the file.
+local function apply_hunks(orig_lines, hunks)
+  local result = {}
+  local cursor = 1
+  for _, hunk in ipairs(hunks) do
+    -- A hunk that removes nothing starts after its old_start line.
+    local first = hunk.old_lines == 0 and hunk.old_start + 1 or hunk.old_start
+    while cursor < first do
+      table.insert(result, orig_lines[cursor])
+      cursor = cursor + 1
+    end
+    for _, line in ipairs(hunk.lines) do
+      local sign, text = line:sub(1, 1), line:sub(2)
+      if sign == "+" then
+        table.insert(result, text)
+      else
+        if orig_lines[cursor] ~= text then
+          return nil, hunk.old_start
+        end
+        if sign == " " then
+          table.insert(result, orig_lines[cursor])
+        end
+        cursor = cursor + 1
+      end
+    end
+  end
+  while cursor <= #orig_lines do
+    table.insert(result, orig_lines[cursor])
+    cursor = cursor + 1
+  end
+  return result
+end
+
+function M.show_change(change)
+  local file_path = change.path
+  if change.unmatched then
+    vim.notify("[claudecode] Could not find old_string in " .. file_path, vim.log.levels.WARN)
     return
   end
+  if change.ambiguous then
+    vim.notify("[claudecode] old_string matches more than once in " .. file_path .. "; showing the first match", vim.log.levels.WARN)
+  end
 
-  local file_path = input.file_path
-  local old_string = input.old_string or ""
-  local new_string = input.new_string or ""
+  -- Notebook hunks are against normalized JSON, not the file's own lines.
+  if not change.old_hash or file_path:match("%.ipynb$") or vim.fn.filereadable(file_path) ~= 1 then
+    return
+  end
 
-  if vim.fn.filereadable(file_path) ~= 1 then
+  if not change.hunks or #change.hunks == 0 then
     return
   end
 
@@ -145,9 +192,20 @@ function M.show(tool_use_id, input)
     return
   end
 
+  local bufnr = vim.fn.bufnr(file_path)
+
+  -- Don't overwrite unsaved edits; have the bridge merge the change in.
+  if bufnr ~= -1 and vim.bo[bufnr].modified then
+    local lines = vim.api.nvim_buf_get_lines(bufnr, 0, -1, false)
+    require("claudecode.bridge").send({
+      method = "merge",
+      params = { tool_id = change.tool_id, content = table.concat(lines, "\n") .. "\n" },
+    })
+    return
+  end
+
   setup_highlights()
 
-  local bufnr = vim.fn.bufnr(file_path)
   if bufnr == -1 then
     vim.cmd("edit " .. vim.fn.fnameescape(file_path))
     bufnr = vim.api.nvim_get_current_buf()
@@ -160,23 +218,24 @@ function M.show(tool_use_id, input)
     end
   end
 
-  local original_lines
-  if vim.bo[bufnr].modified then
-    original_lines = vim.api.nvim_buf_get_lines(bufnr, 0, -1, false)
-  else
-    original_lines = vim.fn.readfile(file_path)
+  local tool_use_id = change.tool_id
+  local original_lines, hash = read_file(file_path)
+  if hash ~= change.old_hash then
+    -- Already written, e.g. under acceptEdits; nothing left to review.
+    if hash ~= change.new_hash then
+      vim.notify("[claudecode] " .. file_path .. " changed since Claude read it; not showing the edit", vim.log.levels.WARN)
+    end
+    return
   end
-  local original_content = table.concat(original_lines, "\n")
-
-  local escaped_new = new_string:gsub("%%", "%%%%")
-  local modified_content = original_content:gsub(vim.pesc(old_string), escaped_new, 1)
-  if modified_content == original_content then
-    vim.notify("[claudecode] Could not find old_string in " .. file_path, vim.log.levels.WARN)
+  local modified_lines, failed_at = apply_hunks(original_lines, change.hunks)
+  if not modified_lines then
+    vim.notify(string.format("[claudecode] Edit does not apply to %s at line %d", file_path, failed_at), vim.log.levels.WARN)
     return
   end
-
-  local modified_lines = vim.split(modified_content, "\n")
-  local hunks = compute_hunks(original_content .. "\n", modified_content .. "\n")
+  local hunks = compute_hunks(
+    table.concat(original_lines, "\n") .. "\n",
+    table.concat(modified_lines, "\n") .. "\n"
+  )
 
   if not hunks or #hunks == 0 then
     vim.notify("[claudecode] No differences found", vim.log.levels.WARN)
@@ -215,10 +274,31 @@ function M.show(tool_use_id, input)
     bufnr = bufnr,
     original_lines = original_lines,
     modified_lines = modified_lines,
+    old_hash = change.old_hash,
+    hunks = change.hunks,
     extmark_ids = extmark_ids,
   }
 end
 
+function M.show_merge(result)
+  local bufnr = vim.fn.bufnr(result.path)
+  if bufnr == -1 or not vim.api.nvim_buf_is_loaded(bufnr) then
+    return
+  end
+  local lines = vim.split(result.content or "", "\n", { plain = true })
+  if lines[#lines] == "" then
+    table.remove(lines)
+  end
+  vim.api.nvim_buf_set_lines(bufnr, 0, -1, false, lines)
+
+  local name = vim.fn.fnamemodify(result.path, ":~:.")
+  if (result.conflicts or 0) > 0 then
+    vim.notify(string.format("[claudecode] Merged Claude's edit into %s with %d conflicts; resolve the markers and save", name, result.conflicts), vim.log.levels.WARN)
+  else
+    vim.notify("[claudecode] Merged Claude's edit into " .. name .. "; review and save", vim.log.levels.INFO)
+  end
+end
+
 function M.accept(tool_use_id)
   local diff = pending_diffs[tool_use_id]
   if not diff then
@@ -231,8 +311,20 @@ function M.accept(tool_use_id)
     return
   end
 
+  -- The file may have changed on disk while the diff was shown.
+  local current_lines, hash = read_file(diff.file_path)
+  if hash ~= diff.old_hash then
+    vim.notify("[claudecode] " .. diff.file_path .. " changed on disk; not accepting the edit", vim.log.levels.ERROR)
+    return
+  end
+  local modified_lines, failed_at = apply_hunks(current_lines, diff.hunks)
+  if not modified_lines then
+    vim.notify(string.format("[claudecode] Edit no longer applies to %s at line %d", diff.file_path, failed_at), vim.log.levels.ERROR)
+    return
+  end
+
   vim.bo[diff.bufnr].modifiable = true
-  vim.fn.writefile(diff.modified_lines, diff.file_path)
+  vim.fn.writefile(modified_lines, diff.file_path)
   vim.bo[diff.bufnr].modified = false
 
   clear_decorations(tool_use_id)
@@ -10,14 +10,6 @@ M.check = function()
     vim.health.ok("Neovim " .. tostring(nvim_version))
   end
 
-  if vim.fn.executable("claude") == 1 then
-    local result = vim.fn.system("claude --version 2>/dev/null")
-    result = vim.trim(result)
-    vim.health.ok("claude CLI: " .. result)
-  else
-    vim.health.error("claude CLI not found in PATH")
-  end
-
   local bridge = require("claudecode.bridge")
   local plugin_dir = vim.fn.fnamemodify(debug.getinfo(1, "S").source:sub(2), ":h:h:h")
   local binary_paths = {
@@ -25,12 +17,14 @@ M.check = function()
     plugin_dir .. "/lua/claudecode/bin/claudecode-bridge",
   }
 
-  local found_binary = false
-  for _, path in ipairs(binary_paths) do
-    if vim.fn.executable(path) == 1 then
-      vim.health.ok("Bridge binary: " .. path)
-      found_binary = true
-      break
+  local config = require("claudecode").config
+  local found_binary = config.binary_path
+  if not found_binary then
+    for _, path in ipairs(binary_paths) do
+      if vim.fn.executable(path) == 1 then
+        found_binary = path
+        break
+      end
     end
   end
 
@@ -39,6 +33,42 @@ M.check = function()
       "Bridge binary not found. Run :lua require('claudecode.build').install()",
       { "cd " .. plugin_dir .. "/rust && cargo build --release" }
     )
+
+    local claude = config.claude_path or "claude"
+    if vim.fn.executable(claude) == 1 then
+      vim.health.ok("claude CLI: " .. claude)
+    else
+      vim.health.error("claude CLI not found: " .. claude)
+    end
+  else
+    vim.health.ok("Bridge binary: " .. found_binary)
+
+    -- The same settings the bridge is configured with, so profiles and env
+    -- pick the CLI and account turns would use.
+    local cmd = { found_binary, "doctor", "--json" }
+    vim.list_extend(cmd, {
+      "--config",
+      vim.json.encode(require("claudecode.bridge").launch_config(config)),
+    })
+    -- Each check is capped at 5s and the last two run together.
+    local result = vim.system(cmd, { text = true }):wait(12000)
+    local ok, report = pcall(vim.json.decode, result.stdout or "")
+    if result.code == 124 then
+      vim.health.warn("Bridge doctor timed out")
+    elseif ok and type(report) == "table" and report.checks then
+      for _, item in ipairs(report.checks) do
+        local msg = item.name .. ": " .. item.message
+        if item.status == "pass" then
+          vim.health.ok(msg)
+        elseif item.status == "warn" then
+          vim.health.warn(msg)
+        else
+          vim.health.error(msg)
+        end
+      end
+    else
+      vim.health.warn("Bridge doctor did not produce a report")
+    end
   end
 
   local api_key = vim.env.ANTHROPIC_API_KEY
@@ -25,9 +25,20 @@ M.config = {
     sessions = "<leader>cl",
   },
   model = nil,
+  model_fallbacks = nil,
   allowed_tools = nil,
   append_system_prompt = nil,
   permission_mode = "acceptEdits",
+  limits = nil,
+  claude_path = nil,
+  env = nil,
+  env_passthrough = nil,
+  profiles = nil,
+  profile = nil,
+  isolation = nil,
+  context_providers = nil,
+  shadow_commits = false,
+  track_changes = false,
   binary_path = nil,
 }
 
@@ -50,6 +61,22 @@ function M.setup(opts)
   require("claudecode.chat").setup()
   require("claudecode.keymaps").setup(M.config)
 
+  -- Keep the bridge informed of open buffers so edits based on a stale read
+  -- can be flagged.
+  local group = vim.api.nvim_create_augroup("claudecode_buffers", { clear = true })
+  vim.api.nvim_create_autocmd({ "BufReadPost", "BufWritePost", "BufModifiedSet", "FileChangedShellPost" }, {
+    group = group,
+    callback = function(ev)
+      require("claudecode.chat").push_buffer_state(ev.buf)
+    end,
+  })
+  vim.api.nvim_create_autocmd("BufUnload", {
+    group = group,
+    callback = function(ev)
+      require("claudecode.chat").push_buffer_state(ev.buf, true)
+    end,
+  })
+
   vim.api.nvim_create_user_command("Claude", function(args)
     if args.args == "" then
       require("claudecode.ui").toggle()
@@ -66,6 +93,50 @@ function M.setup(opts)
     require("claudecode.chat").abort()
   end, { desc = "Abort Claude request" })
 
+  vim.api.nvim_create_user_command("ClaudeSteer", function(args)
+    require("claudecode.chat").steer(args.args)
+  end, { nargs = 1, desc = "Steer the running Claude turn" })
+
+  vim.api.nvim_create_user_command("ClaudeRollback", function(args)
+    require("claudecode.chat").rollback(tonumber(args.args), args.bang)
+  end, { nargs = "?", bang = true, desc = "Undo a Claude turn's file changes" })
+
+  vim.api.nvim_create_user_command("ClaudeDropCheckpoints", function()
+    require("claudecode.chat").delete_checkpoints()
+  end, { desc = "Forget the session's rollback checkpoints" })
+
+  vim.api.nvim_create_user_command("ClaudeChanges", function()
+    require("claudecode.chat").show_changes()
+  end, { desc = "Show the files changed by the last Claude turn" })
+
+  vim.api.nvim_create_user_command("ClaudeWorktree", function(args)
+    local bridge = require("claudecode.bridge")
+    if not bridge.is_running() then
+      vim.notify("[claudecode] Bridge not running", vim.log.levels.WARN)
+      return
+    end
+    if args.args == "merge" then
+      bridge.send({ method = "merge_worktree" })
+    elseif args.args == "discard" then
+      bridge.send({ method = "discard_worktree" })
+    else
+      vim.notify("[claudecode] Usage: :ClaudeWorktree merge|discard", vim.log.levels.WARN)
+    end
+  end, {
+    nargs = 1,
+    complete = function()
+      return { "merge", "discard" }
+    end,
+    desc = "Merge or discard Claude's isolated worktree",
+  })
+
+  vim.api.nvim_create_user_command("ClaudeShadowLog", function()
+    local bridge = require("claudecode.bridge")
+    if bridge.start(M.config) then
+      bridge.send({ method = "shadow_log", params = { cwd = vim.fn.getcwd() } })
+    end
+  end, { desc = "Browse the per-turn shadow commits" })
+
   vim.api.nvim_create_user_command("ClaudeSessions", function()
     require("claudecode.keymaps").session_picker()
   end, { desc = "List Claude sessions" })
@@ -79,6 +150,13 @@ function M.setup(opts)
     end
   end, { desc = "Claude bridge status" })
 
+  vim.api.nvim_create_user_command("ClaudeDoctor", function()
+    local bridge = require("claudecode.bridge")
+    if bridge.start(M.config) then
+      bridge.send({ method = "doctor" })
+    end
+  end, { desc = "Check the claude CLI version and login" })
+
   vim.api.nvim_create_user_command("ClaudeNew", function()
     require("claudecode.chat").new_session()
   end, { desc = "Start new Claude session" })
@@ -0,0 +1,1868 @@
+use crate::buffers::BufferTracker;
+use crate::changeset::TreeSnapshot;
+use crate::checkpoint::CheckpointStore;
+use crate::claude::{unix_now, ClaudeProcess};
+use crate::config::BridgeConfig;
+use crate::context;
+use crate::edits;
+use crate::error::{BridgeError, ErrorCode};
+use crate::hook::PreImages;
+use crate::mentions;
+use crate::protocol::{
+    BusyBehavior, ChatParams, ConfigureParams, Event, Isolation, QueueItem, RollbackParams,
+    Severity, ShadowLogParams, TimeoutPolicy, WorktreeState,
+};
+use crate::session::SessionManager;
+use crate::shadow;
+use crate::usage::UsageTracker;
+use crate::watcher::TurnWatcher;
+use crate::worktree::Worktree;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// How long a timed-out CLI gets to exit after SIGTERM.
+const TERMINATE_GRACE: Duration = Duration::from_secs(3);
+
+/// How long the CLI may keep running after its output ends.
+const EXIT_WAIT: Duration = Duration::from_secs(5);
+
+/// Prompt sent when a failed attempt is retried by resuming its session; the
+/// original prompt is already part of that session.
+const RETRY_PROMPT: &str =
+    "Your previous response was interrupted by an error. Continue where you left off.";
+
+static NEXT_TURN: AtomicU64 = AtomicU64::new(1);
+static NEXT_QUEUE_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_STEER_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone)]
+pub enum SessionTarget {
+    New,
+    Resume(String),
+    /// Whatever session is current when the turn starts (`continue`).
+    Latest,
+}
+
+struct QueuedTurn {
+    queue_id: u64,
+    params: ChatParams,
+    target: SessionTarget,
+    request_id: Option<String>,
+}
+
+/// The turn currently owned by the bridge: the task driving it (including
+/// retries) and the claude process of its current attempt.
+struct ActiveTurn {
+    turn: u64,
+    params: ChatParams,
+    process: Option<ClaudeProcess>,
+    task: Option<JoinHandle<()>>,
+    /// Steering messages no live process could take yet.
+    pending_steers: Vec<(u64, String)>,
+}
+
+enum AttemptOutcome {
+    Finished,
+    Failed {
+        error: BridgeError,
+        session_id: Option<String>,
+        produced_output: bool,
+    },
+    Superseded,
+}
+
+#[derive(Clone)]
+pub struct Bridge {
+    pub session: SessionManager,
+    pub usage: UsageTracker,
+    pub checkpoints: CheckpointStore,
+    pub buffers: BufferTracker,
+    pub pre_images: PreImages,
+    worktree: Arc<Mutex<Option<Worktree>>>,
+    config: Arc<Mutex<BridgeConfig>>,
+    active: Arc<Mutex<Option<ActiveTurn>>>,
+    queue: Arc<Mutex<VecDeque<QueuedTurn>>>,
+    out: mpsc::UnboundedSender<Event>,
+    started: Instant,
+}
+
+impl Bridge {
+    pub fn new(out: mpsc::UnboundedSender<Event>) -> Self {
+        Self {
+            session: SessionManager::new(),
+            usage: UsageTracker::load(),
+            checkpoints: CheckpointStore::new(),
+            buffers: BufferTracker::new(),
+            pre_images: PreImages::new(),
+            worktree: Arc::new(Mutex::new(None)),
+            config: Arc::new(Mutex::new(BridgeConfig::default())),
+            active: Arc::new(Mutex::new(None)),
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+            out,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn uptime_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    pub async fn configure(&self, params: ConfigureParams) {
+        *self.config.lock().await = BridgeConfig::new(params);
+    }
+
+    pub async fn doctor(&self) {
+        let mut params = ChatParams::default();
+        // An unmatched or unknown profile still leaves the plain defaults.
+        let _ = self.config.lock().await.resolve(&mut params);
+        let out = self.out.clone();
+        tokio::spawn(async move {
+            let checks = crate::doctor::run(&params.launch).await;
+            let _ = out.send(Event::Doctor { checks });
+        });
+    }
+
+    pub fn emit(&self, event: Event) {
+        let _ = self.out.send(event);
+    }
+
+    pub fn out(&self) -> &mpsc::UnboundedSender<Event> {
+        &self.out
+    }
+
+    /// Returns whether a turn was running.
+    pub async fn abort_active(&self) -> bool {
+        let Some(active) = self.active.lock().await.take() else {
+            return false;
+        };
+        if let Some(ref process) = active.process {
+            let _ = process.abort().await;
+        }
+        if let Some(task) = active.task {
+            task.abort();
+        }
+        self.checkpoints.seal(active.turn).await;
+        self.session.set_inactive().await;
+        true
+    }
+
+    /// Drops queued prompts and stops the active turn for exit, giving its
+    /// process `TERMINATE_GRACE` to wind down before it is killed.
+    pub async fn shutdown(&self) {
+        self.clear_queue().await;
+        self.pre_images.remove();
+        let Some(mut active) = self.active.lock().await.take() else {
+            return;
+        };
+        // Stop the task first so it cannot schedule a retry.
+        if let Some(task) = active.task.take() {
+            task.abort();
+        }
+        if let Some(ref process) = active.process {
+            let _ = process.terminate(TERMINATE_GRACE).await;
+        }
+        self.checkpoints.seal(active.turn).await;
+        self.session.set_inactive().await;
+    }
+
+    pub async fn submit(
+        &self,
+        params: ChatParams,
+        target: SessionTarget,
+        request_id: Option<String>,
+    ) {
+        // Lock order is queue, then active; `finish_turn` relies on it.
+        let mut queue = self.queue.lock().await;
+        let busy = self.active.lock().await.is_some();
+
+        if busy {
+            match params.on_busy.unwrap_or_default() {
+                BusyBehavior::Queue => {
+                    let queue_id = NEXT_QUEUE_ID.fetch_add(1, Ordering::Relaxed);
+                    queue.push_back(QueuedTurn {
+                        queue_id,
+                        params,
+                        target,
+                        request_id: request_id.clone(),
+                    });
+                    self.emit(Event::Queued {
+                        queue_id,
+                        position: queue.len(),
+                        request_id,
+                    });
+                    return;
+                }
+                BusyBehavior::Reject => {
+                    let err = BridgeError::new(ErrorCode::Busy, "A turn is already running");
+                    self.emit(err.into_event(request_id));
+                    return;
+                }
+                BusyBehavior::Replace => {
+                    self.abort_active().await;
+                }
+            }
+        }
+
+        self.begin(params, target, request_id).await;
+    }
+
+    pub async fn list_queue(&self) -> Vec<QueueItem> {
+        self.queue
+            .lock()
+            .await
+            .iter()
+            .enumerate()
+            .map(|(i, q)| QueueItem {
+                queue_id: q.queue_id,
+                position: i + 1,
+                prompt: q.params.prompt.clone(),
+                request_id: q.request_id.clone(),
+            })
+            .collect()
+    }
+
+    /// Drops a queued prompt. Returns false if it was not (or no longer) queued.
+    pub async fn cancel_queued(&self, queue_id: u64) -> bool {
+        let mut queue = self.queue.lock().await;
+        let before = queue.len();
+        queue.retain(|q| q.queue_id != queue_id);
+        let removed = queue.len() != before;
+        if removed {
+            self.emit(Event::QueueCancelled { queue_id });
+        }
+        removed
+    }
+
+    pub async fn clear_queue(&self) {
+        let drained: Vec<QueuedTurn> = self.queue.lock().await.drain(..).collect();
+        for q in drained {
+            self.emit(Event::QueueCancelled {
+                queue_id: q.queue_id,
+            });
+        }
+    }
+
+    /// Resolves the session for a turn and starts it. Boxed with an explicit
+    /// `Send` bound because finishing a turn starts the next queued one,
+    /// which would otherwise make the future's type recursive.
+    fn begin(
+        &self,
+        mut params: ChatParams,
+        target: SessionTarget,
+        request_id: Option<String>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(async move {
+            let resume_session = match target {
+                SessionTarget::New => None,
+                SessionTarget::Resume(sid) => Some(sid),
+                SessionTarget::Latest => match self.session.get_session_id().await {
+                    Some(sid) => {
+                        // The session lives in its profile's config dir, and
+                        // in its worktree when isolated.
+                        if params.profile.is_none() {
+                            params.profile = self.session.get_state().await.profile;
+                        }
+                        if params.isolation.is_none() && self.worktree.lock().await.is_some() {
+                            params.isolation = Some(Isolation::Worktree);
+                        }
+                        Some(sid)
+                    }
+                    None => {
+                        let err =
+                            BridgeError::new(ErrorCode::NoSession, "No active session to continue");
+                        self.emit(err.into_event(request_id));
+                        return;
+                    }
+                },
+            };
+            self.start_turn(params, resume_session, request_id).await;
+        })
+    }
+
+    async fn start_turn(
+        &self,
+        mut params: ChatParams,
+        resume_session: Option<String>,
+        request_id: Option<String>,
+    ) {
+        if let Err(e) = self.usage.check().await {
+            self.emit(e.into_event(request_id));
+            return;
+        }
+
+        if let Err(e) = self.config.lock().await.resolve(&mut params) {
+            self.emit(e.into_event(request_id));
+            return;
+        }
+        // Context is read where the turn will run.
+        if params.isolation == Some(Isolation::Worktree) {
+            if let Err(e) = self.enter_worktree(&mut params).await {
+                self.emit(e.into_event(request_id));
+                return;
+            }
+        }
+        self.gather_context(&mut params).await;
+        self.session.set_profile(params.profile.clone()).await;
+        let turn = NEXT_TURN.fetch_add(1, Ordering::Relaxed);
+        // Hold the slot while spawning so the task cannot finish and clear
+        // it before its handle is stored.
+        let mut active = self.active.lock().await;
+        let bridge = self.clone();
+        let turn_params = params.clone();
+        let task = tokio::spawn(async move {
+            bridge
+                .run_turn(turn, params, resume_session, request_id)
+                .await;
+        });
+        *active = Some(ActiveTurn {
+            turn,
+            params: turn_params,
+            process: None,
+            task: Some(task),
+            pending_steers: Vec::new(),
+        });
+    }
+
+    /// Mentions that match nothing and providers that fail are reported
+    /// and left out.
+    async fn gather_context(&self, params: &mut ChatParams) {
+        let cwd = match params.cwd {
+            Some(ref cwd) => PathBuf::from(cwd),
+            None => std::env::current_dir().unwrap_or_default(),
+        };
+        let mut sections: Vec<String> = params.context.take().into_iter().collect();
+
+        if params.prompt.contains('@') {
+            let prompt = params.prompt.clone();
+            let root = cwd.clone();
+            if let Ok(expansion) =
+                tokio::task::spawn_blocking(move || mentions::expand(&prompt, &root)).await
+            {
+                if !expansion.unresolved.is_empty() {
+                    self.emit(Event::UnresolvedMentions {
+                        mentions: expansion.unresolved,
+                    });
+                }
+                for refused in expansion.refused {
+                    self.emit(Event::Log {
+                        severity: Severity::Warning,
+                        message: format!("Not inlined: {}", refused),
+                    });
+                }
+                sections.extend(expansion.context);
+            }
+        }
+
+        for request in std::mem::take(&mut params.context_providers) {
+            match context::render(&cwd, &request).await {
+                Ok(section) => sections.push(section),
+                Err(e) => self.emit(Event::Log {
+                    severity: Severity::Warning,
+                    message: format!("Context provider failed: {}", e.message),
+                }),
+            }
+        }
+        if !sections.is_empty() {
+            params.context = Some(sections.join("\n\n"));
+        }
+    }
+
+    /// Points `params` at the isolation worktree, creating it on first use.
+    async fn enter_worktree(&self, params: &mut ChatParams) -> Result<(), BridgeError> {
+        let cwd = match params.cwd {
+            Some(ref cwd) => PathBuf::from(cwd),
+            None => std::env::current_dir().map_err(|e| {
+                BridgeError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("No working directory: {}", e),
+                )
+            })?,
+        };
+        let cwd = std::fs::canonicalize(&cwd).unwrap_or(cwd);
+        let mut worktree = self.worktree.lock().await;
+        let wt = match worktree.as_ref() {
+            Some(wt) if cwd.starts_with(&wt.repo) || cwd.starts_with(&wt.path) => wt,
+            Some(wt) => {
+                return Err(BridgeError::new(
+                    ErrorCode::Busy,
+                    format!(
+                        "A worktree of {} is open; merge or discard it first",
+                        wt.repo.display()
+                    ),
+                ))
+            }
+            None => {
+                let wt = Worktree::create(&cwd).await?;
+                self.emit(Event::Worktree {
+                    path: wt.path.display().to_string(),
+                    branch: wt.branch.clone(),
+                    state: WorktreeState::Created,
+                });
+                worktree.insert(wt)
+            }
+        };
+        params.cwd = Some(wt.cwd_for(&cwd).display().to_string());
+        Ok(())
+    }
+
+    /// Takes back the newest worktree an earlier bridge left open in the
+    /// repository containing `cwd`, so it can still be merged or discarded.
+    pub async fn recover_worktree(&self, cwd: &Path) {
+        let Ok(mut left) = Worktree::leftover(cwd).await else {
+            return;
+        };
+        let mut worktree = self.worktree.lock().await;
+        if worktree.is_some() {
+            return;
+        }
+        let Some(wt) = left.pop() else { return };
+        self.emit(Event::Worktree {
+            path: wt.path.display().to_string(),
+            branch: wt.branch.clone(),
+            state: WorktreeState::Restored,
+        });
+        for other in left {
+            self.emit(Event::Log {
+                severity: Severity::Warning,
+                message: format!(
+                    "Worktree {} on {} was left open; remove it with git worktree remove",
+                    other.path.display(),
+                    other.branch
+                ),
+            });
+        }
+        *worktree = Some(wt);
+    }
+
+    pub async fn close_worktree(&self, merge: bool, request_id: Option<String>) {
+        if self.active.lock().await.is_some() {
+            let err = BridgeError::new(
+                ErrorCode::Busy,
+                "Cannot close the worktree while a turn is running",
+            );
+            self.emit(err.into_event(request_id));
+            return;
+        }
+        let mut worktree = self.worktree.lock().await;
+        let Some(wt) = worktree.as_ref() else {
+            let err = BridgeError::new(ErrorCode::InvalidRequest, "No worktree is open");
+            self.emit(err.into_event(request_id));
+            return;
+        };
+        let result = if merge {
+            let session = self.session.get_session_id().await;
+            let message = format!("Claude session {}", session.as_deref().unwrap_or("changes"));
+            wt.merge(&message).await
+        } else {
+            wt.discard().await
+        };
+        match result {
+            Ok(()) => {
+                self.emit(Event::Worktree {
+                    path: wt.path.display().to_string(),
+                    branch: wt.branch.clone(),
+                    state: if merge {
+                        WorktreeState::Merged
+                    } else {
+                        WorktreeState::Discarded
+                    },
+                });
+                *worktree = None;
+            }
+            Err(e) => self.emit(e.into_event(request_id)),
+        }
+    }
+
+    /// Injects `text` into the running turn, or holds it for the next turn
+    /// boundary when the CLI cannot take it right now.
+    pub async fn steer(&self, text: String, request_id: Option<String>) {
+        let mut active = self.active.lock().await;
+        let Some(a) = active.as_mut() else {
+            let err = BridgeError::new(
+                ErrorCode::NoSession,
+                "No running turn to steer; send a continue instead",
+            );
+            self.emit(err.into_event(request_id));
+            return;
+        };
+
+        let steer_id = NEXT_STEER_ID.fetch_add(1, Ordering::Relaxed);
+        let delivered = match a.process {
+            Some(ref p) => p.send_user_message(&text).await.is_ok(),
+            None => false,
+        };
+        if !delivered {
+            a.pending_steers.push((steer_id, text));
+        }
+        self.emit(Event::SteerAck {
+            steer_id,
+            delivered,
+            request_id,
+        });
+    }
+
+    async fn run_turn(
+        &self,
+        turn: u64,
+        mut params: ChatParams,
+        mut resume_session: Option<String>,
+        request_id: Option<String>,
+    ) {
+        self.session.begin_turn().await;
+        let root = match params.cwd {
+            Some(ref cwd) => Some(PathBuf::from(cwd)),
+            None => std::env::current_dir().ok(),
+        };
+        // Captured while the CLI starts up; no tool can run before the
+        // model's first reply.
+        let track_changes = self.config.lock().await.track_changes();
+        let baseline = match root.clone() {
+            Some(root) if track_changes => Some(tokio::task::spawn_blocking(move || {
+                TreeSnapshot::capture(&root)
+            })),
+            _ => None,
+        };
+        let watcher = root.clone().and_then(|root| {
+            TurnWatcher::start(root, self.session.clone(), self.out.clone(), track_changes)
+        });
+        // Retries replace the prompt with a nudge; records keep the original.
+        let prompt = params.prompt.clone();
+        let policy = params.retry.clone().unwrap_or_default();
+        let mut fallbacks = std::mem::take(&mut params.model_fallbacks).into_iter();
+        let mut attempt = 1;
+        let mut watchdog = Watchdog::new(params.timeouts.clone().unwrap_or_default());
+
+        loop {
+            let outcome = self
+                .run_attempt(
+                    turn,
+                    &params,
+                    resume_session.clone(),
+                    request_id.clone(),
+                    &mut watchdog,
+                )
+                .await;
+
+            match outcome {
+                AttemptOutcome::Finished => {
+                    // The checkpoint index numbers the turn for the shadow
+                    // log too, so both agree across restarts.
+                    let number = match self.session.get_session_id().await {
+                        Some(sid) => self.checkpoints.number(turn, &sid, &prompt).await,
+                        None => None,
+                    };
+                    self.close_turn(turn, baseline, watcher).await;
+                    self.emit(Event::Done);
+                    // Still before the slot is released, so the next turn
+                    // cannot touch the tree while it is being recorded.
+                    self.shadow_commit(root.as_deref(), &prompt, number).await;
+                }
+                AttemptOutcome::Superseded => return,
+                AttemptOutcome::Failed {
+                    error,
+                    session_id,
+                    produced_output,
+                } => {
+                    if !produced_output && error.code.is_model_failure() {
+                        if let Some(next) = fallbacks.next() {
+                            self.emit(Event::ModelFallback {
+                                from: params.model.replace(next.clone()),
+                                to: next,
+                                reason: error.message,
+                            });
+                            rerun_in_session(&mut params, &mut resume_session, session_id);
+                            continue;
+                        }
+                    }
+
+                    if policy.should_retry(error.code, attempt) {
+                        let delay_ms = policy.delay_ms(attempt, error.retry_after);
+                        attempt += 1;
+                        self.emit(Event::Retrying {
+                            attempt,
+                            delay_ms,
+                            reason: error.message,
+                        });
+                        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                        rerun_in_session(&mut params, &mut resume_session, session_id);
+                        continue;
+                    }
+                    self.close_turn(turn, baseline, watcher).await;
+                    self.emit(error.into_event(request_id.clone()));
+                    self.emit(Event::Done);
+                }
+            }
+            break;
+        }
+
+        self.finish_turn(turn).await;
+    }
+
+    async fn close_turn(
+        &self,
+        turn: u64,
+        baseline: Option<JoinHandle<TreeSnapshot>>,
+        watcher: Option<TurnWatcher>,
+    ) {
+        self.checkpoints.seal(turn).await;
+        self.pre_images.clear().await;
+        drop(watcher);
+        let Some(baseline) = baseline else {
+            return;
+        };
+        let Ok(baseline) = baseline.await else {
+            return;
+        };
+        if let Ok(changeset) = tokio::task::spawn_blocking(move || baseline.changeset()).await {
+            self.emit(changeset);
+        }
+    }
+
+    /// Records the working tree on the shadow ref as turn `number` of the
+    /// session, when enabled.
+    async fn shadow_commit(&self, cwd: Option<&Path>, prompt: &str, number: Option<u32>) {
+        if !self.config.lock().await.shadow_commits() {
+            return;
+        }
+        let state = self.session.get_state().await;
+        let (Some(cwd), Some(session_id)) = (cwd, state.session_id) else {
+            return;
+        };
+        if let Err(e) =
+            shadow::commit_turn(cwd, &session_id, number, prompt, state.turn.cost_usd).await
+        {
+            self.emit(Event::Log {
+                severity: Severity::Warning,
+                message: format!("Shadow commit failed: {}", e.message),
+            });
+        }
+    }
+
+    pub async fn shadow_log(&self, params: ShadowLogParams, request_id: Option<String>) {
+        let cwd = match params.cwd {
+            Some(cwd) => PathBuf::from(cwd),
+            None => std::env::current_dir().unwrap_or_default(),
+        };
+        match shadow::log(&cwd).await {
+            Ok(mut entries) => {
+                if let Some(sid) = params.session_id {
+                    entries.retain(|e| e.session_id == sid);
+                }
+                self.emit(Event::ShadowLog { entries });
+            }
+            Err(e) => self.emit(e.into_event(request_id)),
+        }
+    }
+
+    /// Releases the active slot held by `turn` and starts what comes next:
+    /// steering messages that missed the turn, then the next queued prompt.
+    async fn finish_turn(&self, turn: u64) {
+        let mut queue = self.queue.lock().await;
+        let finished = {
+            let mut active = self.active.lock().await;
+            if active.as_ref().is_none_or(|a| a.turn != turn) {
+                return;
+            }
+            active.take()
+        };
+
+        if let Some(finished) = finished.filter(|a| !a.pending_steers.is_empty()) {
+            let mut texts = Vec::new();
+            for (steer_id, text) in finished.pending_steers {
+                self.emit(Event::SteerAck {
+                    steer_id,
+                    delivered: true,
+                    request_id: None,
+                });
+                texts.push(text);
+            }
+            let params = ChatParams {
+                prompt: texts.join("\n\n"),
+                context: None,
+                ..finished.params
+            };
+            self.begin(params, SessionTarget::Latest, None).await;
+            return;
+        }
+
+        if let Some(next) = queue.pop_front() {
+            self.emit(Event::QueueStarted {
+                queue_id: next.queue_id,
+                request_id: next.request_id.clone(),
+            });
+            for (i, q) in queue.iter().enumerate() {
+                self.emit(Event::Queued {
+                    queue_id: q.queue_id,
+                    position: i + 1,
+                    request_id: q.request_id.clone(),
+                });
+            }
+            self.begin(next.params, next.target, next.request_id).await;
+        }
+    }
+
+    /// Undoes `turn` and every later turn of a session. Refused while a turn
+    /// is running, since it could be writing the same files.
+    pub async fn rollback(&self, params: RollbackParams, request_id: Option<String>) {
+        if self.active.lock().await.is_some() {
+            let err = BridgeError::new(ErrorCode::Busy, "Cannot roll back while a turn is running");
+            self.emit(err.into_event(request_id));
+            return;
+        }
+        let Some(session_id) = self.session_or_current(params.session_id).await else {
+            let err = BridgeError::new(ErrorCode::NoSession, "No session to roll back");
+            self.emit(err.into_event(request_id));
+            return;
+        };
+        match self
+            .checkpoints
+            .rollback(&session_id, params.turn, params.force)
+            .await
+        {
+            Ok(report) => self.emit(Event::RollbackResult {
+                session_id,
+                turn: params.turn,
+                restored: report.restored,
+                conflicts: report.conflicts,
+            }),
+            Err(e) => self.emit(e.into_event(request_id)),
+        }
+    }
+
+    pub async fn list_checkpoints(&self, session_id: Option<String>, request_id: Option<String>) {
+        let Some(session_id) = self.session_or_current(session_id).await else {
+            let err = BridgeError::new(ErrorCode::NoSession, "No session to list checkpoints for");
+            self.emit(err.into_event(request_id));
+            return;
+        };
+        let checkpoints = self.checkpoints.list(&session_id).await;
+        self.emit(Event::Checkpoints {
+            session_id,
+            checkpoints,
+        });
+    }
+
+    pub async fn delete_checkpoints(&self, session_id: Option<String>, request_id: Option<String>) {
+        if self.active.lock().await.is_some() {
+            let err = BridgeError::new(
+                ErrorCode::Busy,
+                "Cannot delete checkpoints while a turn is running",
+            );
+            self.emit(err.into_event(request_id));
+            return;
+        }
+        let Some(session_id) = self.session_or_current(session_id).await else {
+            let err =
+                BridgeError::new(ErrorCode::NoSession, "No session to delete checkpoints for");
+            self.emit(err.into_event(request_id));
+            return;
+        };
+        self.checkpoints.delete(&session_id).await;
+        self.emit(Event::Checkpoints {
+            session_id,
+            checkpoints: Vec::new(),
+        });
+    }
+
+    async fn session_or_current(&self, session_id: Option<String>) -> Option<String> {
+        match session_id {
+            Some(sid) => Some(sid),
+            None => self.session.get_session_id().await,
+        }
+    }
+
+    pub async fn status(&self) -> Event {
+        let queue_length = self.queue.lock().await.len();
+        let (params, pid) = match self.active.lock().await.as_ref() {
+            Some(a) => (
+                Some(a.params.clone()),
+                a.process.as_ref().and_then(|p| p.pid()),
+            ),
+            None => (None, None),
+        };
+        let state = self.session.get_state().await;
+        let running = params.is_some();
+        let started = state.turn.started.filter(|_| running);
+        let elapsed_ms = started.map(|t| t.elapsed().as_millis() as u64);
+        let cwd = params.as_ref().and_then(|p| p.cwd.clone()).or_else(|| {
+            std::env::current_dir()
+                .ok()
+                .map(|d| d.display().to_string())
+        });
+        Event::Status {
+            active: state.active,
+            session_id: state.session_id,
+            model: state
+                .model
+                .or_else(|| params.as_ref().and_then(|p| p.model.clone())),
+            cwd,
+            permission_mode: params.as_ref().and_then(|p| p.permission_mode.clone()),
+            turn_started_at: started.map(|t| unix_now().saturating_sub(t.elapsed().as_secs())),
+            elapsed_ms,
+            output_tokens: if running { state.turn.output_tokens } else { 0 },
+            tool_calls: if running { state.turn.tool_calls } else { 0 },
+            current_tool: state.turn.current_tool.filter(|_| running),
+            queue_length,
+            pid,
+            total_cost_usd: state.total_cost_usd,
+            profile: params.as_ref().map_or(state.profile, |p| p.profile.clone()),
+            worktree: self
+                .worktree
+                .lock()
+                .await
+                .as_ref()
+                .map(|wt| wt.path.display().to_string()),
+        }
+    }
+
+    async fn heartbeat(&self) -> Event {
+        let turn = self.session.get_state().await.turn;
+        Event::Heartbeat {
+            elapsed_ms: turn
+                .started
+                .map(|t| t.elapsed().as_millis() as u64)
+                .unwrap_or(0),
+            output_tokens: turn.output_tokens,
+            current_tool: turn.current_tool,
+        }
+    }
+
+    async fn terminate_attempt(&self, turn: u64) {
+        if let Some(process) = self.attempt_process(turn).await {
+            let _ = process.terminate(TERMINATE_GRACE).await;
+        }
+    }
+
+    /// The process of `turn`'s current attempt, to signal or wait on without
+    /// holding the slot.
+    async fn attempt_process(&self, turn: u64) -> Option<ClaudeProcess> {
+        let active = self.active.lock().await;
+        active
+            .as_ref()
+            .filter(|a| a.turn == turn)
+            .and_then(|a| a.process.clone())
+    }
+
+    /// Runs one claude process for `turn`, forwarding its events. Terminal
+    /// errors and `Done` are held back so the caller can decide to retry.
+    async fn run_attempt(
+        &self,
+        turn: u64,
+        params: &ChatParams,
+        resume_session: Option<String>,
+        request_id: Option<String>,
+        watchdog: &mut Watchdog,
+    ) -> AttemptOutcome {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Event>();
+        let process = match ClaudeProcess::spawn(
+            params,
+            resume_session.clone(),
+            self.session.clone(),
+            request_id.clone(),
+            tx,
+            self.pre_images.settings().as_deref(),
+        )
+        .await
+        {
+            Ok(p) => p,
+            Err(error) => {
+                return AttemptOutcome::Failed {
+                    error,
+                    session_id: resume_session,
+                    produced_output: false,
+                }
+            }
+        };
+
+        {
+            let mut active = self.active.lock().await;
+            match active.as_mut() {
+                Some(a) if a.turn == turn => {
+                    for (steer_id, text) in std::mem::take(&mut a.pending_steers) {
+                        if process.send_user_message(&text).await.is_ok() {
+                            self.emit(Event::SteerAck {
+                                steer_id,
+                                delivered: true,
+                                request_id: None,
+                            });
+                        } else {
+                            a.pending_steers.push((steer_id, text));
+                        }
+                    }
+                    a.process = Some(process);
+                }
+                _ => {
+                    let _ = process.abort().await;
+                    return AttemptOutcome::Superseded;
+                }
+            }
+        }
+
+        let mut session_id = resume_session;
+        let mut failure: Option<BridgeError> = None;
+        let mut saw_done = false;
+        let mut produced_output = false;
+        watchdog.attempt_started();
+        let mut heartbeat = params
+            .heartbeat_ms
+            .filter(|ms| *ms > 0)
+            .map(|ms| tokio::time::interval(Duration::from_millis(ms)));
+
+        loop {
+            let event = tokio::select! {
+                event = rx.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = tokio::time::sleep_until(watchdog.next_deadline()) => {
+                    match watchdog.check() {
+                        WatchdogAction::Wait => {}
+                        WatchdogAction::Stalled(idle_ms) => self.emit(Event::Stalled { idle_ms }),
+                        WatchdogAction::TimedOut(message) => {
+                            failure = Some(BridgeError::new(ErrorCode::Timeout, message));
+                            self.terminate_attempt(turn).await;
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                _ = async {
+                    match heartbeat.as_mut() {
+                        Some(interval) => interval.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.emit(self.heartbeat().await);
+                    continue;
+                }
+            };
+            if !matches!(event, Event::Log { .. }) {
+                watchdog.activity();
+            }
+
+            // Derived events sent right after the one they describe.
+            let mut follow_ups = Vec::new();
+            match event {
+                Event::Done => {
+                    saw_done = true;
+                    break;
+                }
+                Event::Error {
+                    code,
+                    message,
+                    fatal: true,
+                    retry_after,
+                    ..
+                } => {
+                    failure = Some(BridgeError {
+                        retry_after,
+                        ..BridgeError::new(code, message)
+                    });
+                    continue;
+                }
+                Event::Init {
+                    session_id: ref sid,
+                    ..
+                } => {
+                    session_id = Some(sid.clone());
+                }
+                Event::UsageLimit { reset_at, limited } => {
+                    self.usage.record(reset_at, limited, &self.out).await;
+                }
+                Event::ToolUse {
+                    ref tool,
+                    ref id,
+                    ref input,
+                } => {
+                    produced_output = true;
+                    watchdog.tool_started(id);
+                    self.session.tool_started(tool.clone(), id.clone()).await;
+                    let cwd = params.cwd.as_deref();
+                    if let Some(path) = edits::read_path(tool, input, cwd) {
+                        self.buffers.read(&path).await;
+                    }
+                    if let Some(path) = edits::target_path(tool, input, cwd) {
+                        let before = self.pre_images.take(id, &path).await;
+                        if let (Some(sid), Ok(before)) = (session_id.as_deref(), &before) {
+                            self.checkpoints
+                                .snapshot(turn, sid, &params.prompt, &path, before.clone())
+                                .await;
+                        }
+                        follow_ups.extend(self.buffers.check(id, &path).await);
+                        let proposal = match before {
+                            Ok(before) => edits::propose(tool, id, input, cwd, before.as_deref()),
+                            Err(_) => None,
+                        };
+                        if let Some(proposal) = proposal {
+                            self.buffers.proposed(id, &path, &proposal).await;
+                            follow_ups.push(proposal.event);
+                        }
+                    }
+                }
+                Event::ToolResult { ref id, .. } => {
+                    watchdog.tool_finished(Some(id));
+                    self.session.tool_finished().await;
+                }
+                Event::TextChunk { .. } | Event::Text { .. } => {
+                    produced_output = true;
+                    // Claude only writes again once its tool calls are done.
+                    watchdog.tool_finished(None);
+                    self.session.tool_finished().await;
+                }
+                _ => {}
+            }
+            self.emit(event);
+            for follow_up in follow_ups {
+                self.emit(follow_up);
+            }
+        }
+
+        let Some(process) = self.attempt_process(turn).await else {
+            return AttemptOutcome::Superseded;
+        };
+        // A CLI that closed stdout but never exits is stopped.
+        let status = match tokio::time::timeout(EXIT_WAIT, process.wait()).await {
+            Ok(status) => status.ok(),
+            Err(_) => {
+                let _ = process.terminate(TERMINATE_GRACE).await;
+                process.wait().await.ok()
+            }
+        };
+
+        if let Some(error) = failure {
+            return AttemptOutcome::Failed {
+                error,
+                session_id,
+                produced_output,
+            };
+        }
+        if !saw_done {
+            if let Some(status) = status.filter(|s| !s.success()) {
+                let error = BridgeError::new(
+                    ErrorCode::CliExitNonzero,
+                    format!("claude exited with {}", status),
+                );
+                return AttemptOutcome::Failed {
+                    error,
+                    session_id,
+                    produced_output,
+                };
+            }
+        }
+        AttemptOutcome::Finished
+    }
+}
+
+/// Points a rerun of a failed attempt at the session it left behind.
+fn rerun_in_session(
+    params: &mut ChatParams,
+    resume_session: &mut Option<String>,
+    session_id: Option<String>,
+) {
+    if let Some(sid) = session_id {
+        *resume_session = Some(sid);
+        params.prompt = RETRY_PROMPT.to_string();
+        params.context = None;
+    }
+}
+
+enum WatchdogAction {
+    Wait,
+    Stalled(u64),
+    TimedOut(String),
+}
+
+/// Tools such as a long build are silent by nature, so idle time stands
+/// still while one runs.
+struct Watchdog {
+    limits: TimeoutPolicy,
+    started: Instant,
+    last_activity: Instant,
+    stalls: u32,
+    running_tools: Vec<String>,
+}
+
+impl Watchdog {
+    fn new(limits: TimeoutPolicy) -> Self {
+        let now = Instant::now();
+        Self {
+            limits,
+            started: now,
+            last_activity: now,
+            stalls: 0,
+            running_tools: Vec::new(),
+        }
+    }
+
+    /// A new process starts; the turn's clock keeps running.
+    fn attempt_started(&mut self) {
+        self.running_tools.clear();
+        self.activity();
+    }
+
+    fn activity(&mut self) {
+        self.last_activity = Instant::now();
+        self.stalls = 0;
+    }
+
+    fn tool_started(&mut self, id: &str) {
+        self.running_tools.push(id.to_string());
+    }
+
+    fn tool_finished(&mut self, id: Option<&str>) {
+        match id {
+            Some(id) => self.running_tools.retain(|t| t != id),
+            None => self.running_tools.clear(),
+        }
+    }
+
+    fn idle_paused(&self) -> bool {
+        !self.running_tools.is_empty()
+    }
+
+    fn next_deadline(&self) -> Instant {
+        let ms = Duration::from_millis;
+        let idle = !self.idle_paused();
+        [
+            self.limits.turn_timeout_ms.map(|t| self.started + ms(t)),
+            self.limits
+                .idle_timeout_ms
+                .filter(|_| idle)
+                .map(|t| self.last_activity + ms(t)),
+            self.limits
+                .stall_warning_ms
+                .filter(|t| idle && *t > 0)
+                .map(|t| self.last_activity + ms(t * (self.stalls as u64 + 1))),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        // Nothing to watch; park far in the future.
+        .unwrap_or_else(|| Instant::now() + Duration::from_secs(86_400))
+    }
+
+    fn check(&mut self) -> WatchdogAction {
+        let now = Instant::now();
+        let idle_ms = (now - self.last_activity).as_millis() as u64;
+
+        if let Some(limit) = self.limits.turn_timeout_ms {
+            if (now - self.started).as_millis() as u64 >= limit {
+                return WatchdogAction::TimedOut(format!("Turn exceeded {}ms", limit));
+            }
+        }
+        if self.idle_paused() {
+            return WatchdogAction::Wait;
+        }
+        if let Some(limit) = self.limits.idle_timeout_ms {
+            if idle_ms >= limit {
+                return WatchdogAction::TimedOut(format!(
+                    "No output from claude for {}ms",
+                    idle_ms
+                ));
+            }
+        }
+        if let Some(interval) = self.limits.stall_warning_ms.filter(|t| *t > 0) {
+            if idle_ms >= interval * (self.stalls as u64 + 1) {
+                self.stalls += 1;
+                return WatchdogAction::Stalled(idle_ms);
+            }
+        }
+        WatchdogAction::Wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        ContextProvider, ContextRequest, LaunchOptions, ResourceLimits, RetryPolicy,
+    };
+    use std::os::unix::fs::PermissionsExt;
+
+    const INIT: &str =
+        r#"echo '{"type":"system","subtype":"init","session_id":"s1","model":"fake"}'"#;
+    const RESULT: &str =
+        r#"echo '{"type":"result","total_cost_usd":0,"duration_ms":1,"usage":{}}'"#;
+    const OVERLOADED: &str = r#"echo '{"type":"result","is_error":true,"result":"API Error: 529 Overloaded","total_cost_usd":0,"duration_ms":1,"usage":{}}'"#;
+
+    /// A scratch directory holding a stub `claude` that runs `script`, and a
+    /// `work` directory for turns to run in. The stub appends its arguments
+    /// to `args` and its first stdin line to `prompts` on every run.
+    fn stub_cli(name: &str, script: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("claudecode-bridge-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("work")).unwrap();
+        let cli = dir.join("claude");
+        std::fs::write(
+            &cli,
+            format!(
+                "#!/bin/sh\nhere=$(dirname \"$0\")\necho \"$@\" >> \"$here/args\"\nread line\necho \"$line\" >> \"$here/prompts\"\n{}\n",
+                script
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&cli, std::fs::Permissions::from_mode(0o755)).unwrap();
+        dir
+    }
+
+    fn chat(dir: &Path, prompt: &str) -> ChatParams {
+        ChatParams {
+            prompt: prompt.to_string(),
+            cwd: Some(dir.join("work").display().to_string()),
+            launch: LaunchOptions {
+                claude_path: Some(dir.join("claude").display().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// A bridge that keeps no usage or checkpoint state on disk.
+    fn bridge() -> (Bridge, mpsc::UnboundedReceiver<Event>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let defaults = Bridge::new(tx);
+        defaults.pre_images.remove();
+        let bridge = Bridge {
+            usage: UsageTracker::load_from(None),
+            checkpoints: CheckpointStore::at(None),
+            pre_images: PreImages::under(None),
+            ..defaults
+        };
+        (bridge, rx)
+    }
+
+    /// Events up to and including the next `done`.
+    async fn until_done(rx: &mut mpsc::UnboundedReceiver<Event>) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+                .await
+                .expect("no done event")
+                .expect("bridge output closed");
+            let done = matches!(event, Event::Done);
+            events.push(event);
+            if done {
+                return events;
+            }
+        }
+    }
+
+    /// Events up to and including the first one matching `pred`.
+    async fn until(
+        rx: &mut mpsc::UnboundedReceiver<Event>,
+        pred: impl Fn(&Event) -> bool,
+    ) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+                .await
+                .expect("expected event never came")
+                .expect("bridge output closed");
+            let found = pred(&event);
+            events.push(event);
+            if found {
+                return events;
+            }
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn retries(events: &[Event]) -> Vec<(u32, u64)> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Retrying {
+                    attempt, delay_ms, ..
+                } => Some((*attempt, *delay_ms)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn error_codes(events: &[Event]) -> Vec<ErrorCode> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Error { code, .. } => Some(*code),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn fast_retries() -> Option<RetryPolicy> {
+        Some(RetryPolicy {
+            max_attempts: 3,
+            initial_delay_ms: 50,
+            max_delay_ms: 1000,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_retries_back_off_until_attempts_run_out() {
+        let dir = stub_cli("retry-exhausted", &format!("{}\n{}", INIT, OVERLOADED));
+        let (bridge, mut rx) = bridge();
+        let params = ChatParams {
+            retry: fast_retries(),
+            ..chat(&dir, "hello")
+        };
+
+        let started = std::time::Instant::now();
+        bridge.submit(params, SessionTarget::New, None).await;
+        let events = until_done(&mut rx).await;
+
+        assert_eq!(retries(&events), vec![(2, 50), (3, 100)]);
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert_eq!(error_codes(&events), vec![ErrorCode::Overloaded]);
+        let args = lines(&dir.join("args"));
+        assert_eq!(args.len(), 3);
+        assert!(!args[0].contains("--resume"));
+        assert!(args[1].contains("--resume s1") && args[2].contains("--resume s1"));
+        let prompts = lines(&dir.join("prompts"));
+        assert!(prompts[0].contains("hello"));
+        assert!(prompts[1].contains("interrupted"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_retry_recovers_from_transient_failure() {
+        let script = format!(
+            "{}\nif [ $(wc -l < \"$here/args\") -eq 1 ]; then {}; else {}; fi",
+            INIT, OVERLOADED, RESULT
+        );
+        let dir = stub_cli("retry-recovers", &script);
+        let (bridge, mut rx) = bridge();
+        let params = ChatParams {
+            retry: fast_retries(),
+            ..chat(&dir, "hello")
+        };
+
+        bridge.submit(params, SessionTarget::New, None).await;
+        let events = until_done(&mut rx).await;
+
+        assert_eq!(retries(&events), vec![(2, 50)]);
+        assert!(error_codes(&events).is_empty());
+        assert_eq!(lines(&dir.join("args")).len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_cli_crash_is_not_retried_by_default() {
+        let dir = stub_cli("retry-crash", &format!("{}\nexit 3", INIT));
+        let (bridge, mut rx) = bridge();
+
+        bridge
+            .submit(chat(&dir, "hello"), SessionTarget::New, None)
+            .await;
+        let events = until_done(&mut rx).await;
+
+        assert!(retries(&events).is_empty());
+        assert_eq!(error_codes(&events), vec![ErrorCode::CliExitNonzero]);
+        assert_eq!(lines(&dir.join("args")).len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// A stub whose turns with "slow" in the prompt wait for a `go` file.
+    fn slow_cli(name: &str) -> PathBuf {
+        let script = format!(
+            "{}\ncase \"$line\" in *slow*) while [ ! -f \"$here/go\" ]; do sleep 0.02; done;; esac\n{}",
+            INIT, RESULT
+        );
+        stub_cli(name, &script)
+    }
+
+    fn is_init(event: &Event) -> bool {
+        matches!(event, Event::Init { .. })
+    }
+
+    #[tokio::test]
+    async fn test_queue_runs_prompts_in_order_and_cancels() {
+        let dir = slow_cli("queue");
+        let (bridge, mut rx) = bridge();
+        bridge
+            .submit(chat(&dir, "slow A"), SessionTarget::New, None)
+            .await;
+        until(&mut rx, is_init).await;
+
+        for prompt in ["B", "C"] {
+            bridge
+                .submit(chat(&dir, prompt), SessionTarget::New, None)
+                .await;
+        }
+        let queued: Vec<(u64, usize)> =
+            until(&mut rx, |e| matches!(e, Event::Queued { position: 2, .. }))
+                .await
+                .into_iter()
+                .filter_map(|e| match e {
+                    Event::Queued {
+                        queue_id, position, ..
+                    } => Some((queue_id, position)),
+                    _ => None,
+                })
+                .collect();
+        assert_eq!(queued.len(), 2);
+        let (b, c) = (queued[0].0, queued[1].0);
+        let items = bridge.list_queue().await;
+        let listed: Vec<(u64, usize, &str)> = items
+            .iter()
+            .map(|i| (i.queue_id, i.position, i.prompt.as_str()))
+            .collect();
+        assert_eq!(listed, vec![(b, 1, "B"), (c, 2, "C")]);
+
+        assert!(bridge.cancel_queued(b).await);
+        assert!(!bridge.cancel_queued(b).await);
+        let cancelled = until(&mut rx, |e| matches!(e, Event::QueueCancelled { .. })).await;
+        assert!(
+            matches!(cancelled.last(), Some(Event::QueueCancelled { queue_id }) if *queue_id == b)
+        );
+        assert_eq!(bridge.list_queue().await.len(), 1);
+
+        std::fs::write(dir.join("go"), "").unwrap();
+        until_done(&mut rx).await;
+        let events = until_done(&mut rx).await;
+        assert!(matches!(events[0], Event::QueueStarted { queue_id, .. } if queue_id == c));
+        let prompts = lines(&dir.join("prompts"));
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[0].contains("slow A") && prompts[1].contains("\"C\""));
+        assert!(bridge.list_queue().await.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_busy_prompt_is_rejected() {
+        let dir = slow_cli("reject");
+        let (bridge, mut rx) = bridge();
+        bridge
+            .submit(chat(&dir, "slow A"), SessionTarget::New, None)
+            .await;
+        until(&mut rx, is_init).await;
+
+        let params = ChatParams {
+            on_busy: Some(BusyBehavior::Reject),
+            ..chat(&dir, "B")
+        };
+        bridge
+            .submit(params, SessionTarget::New, Some("r2".to_string()))
+            .await;
+        let events = until(&mut rx, |e| matches!(e, Event::Error { .. })).await;
+        assert!(matches!(
+            events.last(),
+            Some(Event::Error { code: ErrorCode::Busy, request_id: Some(r), .. }) if r == "r2"
+        ));
+        assert!(bridge.list_queue().await.is_empty());
+
+        std::fs::write(dir.join("go"), "").unwrap();
+        until_done(&mut rx).await;
+        assert_eq!(lines(&dir.join("args")).len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_busy_prompt_replaces_running_turn() {
+        let dir = slow_cli("replace");
+        let (bridge, mut rx) = bridge();
+        bridge
+            .submit(chat(&dir, "slow A"), SessionTarget::New, None)
+            .await;
+        until(&mut rx, is_init).await;
+
+        let params = ChatParams {
+            on_busy: Some(BusyBehavior::Replace),
+            ..chat(&dir, "B")
+        };
+        bridge.submit(params, SessionTarget::New, None).await;
+        let events = until_done(&mut rx).await;
+        assert_eq!(events.iter().filter(|e| is_init(e)).count(), 1);
+        let prompts = lines(&dir.join("prompts"));
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[1].contains("\"B\""));
+        // The replaced turn never reports back.
+        assert!(tokio::time::timeout(Duration::from_millis(300), rx.recv())
+            .await
+            .is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_steer_reaches_running_turn() {
+        // Each user message on stdin is answered by one result.
+        let script = format!(
+            "{}\nread steer\necho \"$steer\" >> \"$here/prompts\"\n{}\n{}",
+            INIT, RESULT, RESULT
+        );
+        let dir = stub_cli("steer", &script);
+        let (bridge, mut rx) = bridge();
+        bridge
+            .submit(chat(&dir, "hello"), SessionTarget::New, None)
+            .await;
+        until(&mut rx, is_init).await;
+
+        bridge
+            .steer("use the other API".to_string(), Some("r3".to_string()))
+            .await;
+        let events = until_done(&mut rx).await;
+        assert!(matches!(
+            events.first(),
+            Some(Event::SteerAck { delivered: true, request_id: Some(r), .. }) if r == "r3"
+        ));
+        let prompts = lines(&dir.join("prompts"));
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[1].contains("use the other API"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_steer_without_turn_is_an_error() {
+        let (bridge, mut rx) = bridge();
+        bridge
+            .steer("anyone?".to_string(), Some("r4".to_string()))
+            .await;
+        let events = until(&mut rx, |e| matches!(e, Event::Error { .. })).await;
+        assert!(matches!(
+            events.last(),
+            Some(Event::Error { code: ErrorCode::NoSession, request_id: Some(r), .. }) if r == "r4"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_status_while_idle_running_and_queued() {
+        let dir = slow_cli("status");
+        let (bridge, mut rx) = bridge();
+        let Event::Status {
+            session_id,
+            elapsed_ms,
+            queue_length,
+            pid,
+            current_tool,
+            ..
+        } = bridge.status().await
+        else {
+            panic!("expected status");
+        };
+        assert!(session_id.is_none() && elapsed_ms.is_none() && pid.is_none());
+        assert!(current_tool.is_none());
+        assert_eq!(queue_length, 0);
+
+        let mut params = chat(&dir, "slow A");
+        params.permission_mode = Some("plan".to_string());
+        bridge.submit(params, SessionTarget::New, None).await;
+        until(&mut rx, is_init).await;
+        let Event::Status {
+            session_id,
+            model,
+            cwd,
+            permission_mode,
+            turn_started_at,
+            elapsed_ms,
+            queue_length,
+            pid,
+            ..
+        } = bridge.status().await
+        else {
+            panic!("expected status");
+        };
+        assert_eq!(session_id.as_deref(), Some("s1"));
+        assert_eq!(model.as_deref(), Some("fake"));
+        assert_eq!(cwd, Some(dir.join("work").display().to_string()));
+        assert_eq!(permission_mode.as_deref(), Some("plan"));
+        assert!(turn_started_at.is_some() && elapsed_ms.is_some() && pid.is_some());
+        assert_eq!(queue_length, 0);
+
+        bridge
+            .submit(chat(&dir, "B"), SessionTarget::New, None)
+            .await;
+        until(&mut rx, |e| matches!(e, Event::Queued { .. })).await;
+        let Event::Status { queue_length, .. } = bridge.status().await else {
+            panic!("expected status");
+        };
+        assert_eq!(queue_length, 1);
+
+        std::fs::write(dir.join("go"), "").unwrap();
+        until_done(&mut rx).await;
+        until_done(&mut rx).await;
+        let Event::Status {
+            session_id,
+            elapsed_ms,
+            queue_length,
+            pid,
+            ..
+        } = bridge.status().await
+        else {
+            panic!("expected status");
+        };
+        assert_eq!(session_id.as_deref(), Some("s1"));
+        assert!(elapsed_ms.is_none() && pid.is_none());
+        assert_eq!(queue_length, 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_cli_that_closes_output_but_keeps_running() {
+        let script = format!("{}\necho $$ > \"$here/pid\"\nexec >&- 2>&-\nsleep 30", INIT);
+        let dir = stub_cli("lingering", &script);
+        let (bridge, mut rx) = bridge();
+        bridge
+            .submit(chat(&dir, "hello"), SessionTarget::New, None)
+            .await;
+        until(&mut rx, is_init).await;
+        let pid: libc::pid_t = loop {
+            if let Some(pid) = lines(&dir.join("pid")).first() {
+                break pid.parse().unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+        // Let the turn reach the wait for the process to exit.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let status = tokio::time::timeout(Duration::from_secs(1), bridge.status())
+            .await
+            .expect("status blocked behind the exit wait");
+        let Event::Status { pid: running, .. } = status else {
+            panic!("expected status");
+        };
+        assert_eq!(running, Some(pid as u32));
+        let aborted = tokio::time::timeout(Duration::from_secs(1), bridge.abort_active())
+            .await
+            .expect("abort blocked behind the exit wait");
+        assert!(aborted);
+        // SAFETY: signal 0 only checks that the process exists.
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_resource_limits_apply_to_cli() {
+        let script = format!(
+            "{{ ulimit -t; ulimit -v; cut -d' ' -f19 /proc/$$/stat; }} > \"$here/limits\"\n{}\n{}",
+            INIT, RESULT
+        );
+        let dir = stub_cli("limits", &script);
+        let (bridge, mut rx) = bridge();
+        let mut params = chat(&dir, "hi");
+        params.limits = Some(ResourceLimits {
+            cpu_seconds: Some(600),
+            address_space_mb: Some(4096),
+            nice: Some(5),
+        });
+        bridge.submit(params, SessionTarget::New, None).await;
+        until_done(&mut rx).await;
+        // Niceness is added to the bridge's own.
+        let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+        let base: i32 = stat.split(' ').nth(18).unwrap().parse().unwrap();
+        let applied = lines(&dir.join("limits"));
+        assert_eq!(
+            applied,
+            vec![
+                "600".to_string(),
+                "4194304".to_string(),
+                (base + 5).min(19).to_string()
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_changeset_only_when_tracking_changes() {
+        let script = format!("{}\necho new > made.txt\n{}", INIT, RESULT);
+        let dir = stub_cli("changes", &script);
+        let (bridge, mut rx) = bridge();
+        bridge
+            .submit(chat(&dir, "untracked"), SessionTarget::New, None)
+            .await;
+        let events = until_done(&mut rx).await;
+        assert!(!events.iter().any(|e| matches!(e, Event::Changeset { .. })));
+
+        std::fs::remove_file(dir.join("work/made.txt")).unwrap();
+        bridge
+            .configure(ConfigureParams {
+                track_changes: true,
+                ..Default::default()
+            })
+            .await;
+        bridge
+            .submit(chat(&dir, "tracked"), SessionTarget::New, None)
+            .await;
+        let events = until_done(&mut rx).await;
+        let files: Vec<&str> = events
+            .iter()
+            .find_map(|e| match e {
+                Event::Changeset { files, .. } => {
+                    Some(files.iter().map(|f| f.path.as_str()).collect())
+                }
+                _ => None,
+            })
+            .expect("expected a changeset");
+        assert_eq!(files, vec!["made.txt"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_shadow_turns_continue_after_resume() {
+        let dir = stub_cli("shadow-resume", &format!("{}\n{}", INIT, RESULT));
+        let work = dir.join("work");
+        crate::git::git(&work, &["init", "-q"]).await.unwrap();
+        let store = dir.join("checkpoints");
+        let restarted = || async {
+            let (bridge, rx) = bridge();
+            let bridge = Bridge {
+                checkpoints: CheckpointStore::at(Some(store.clone())),
+                ..bridge
+            };
+            bridge
+                .configure(ConfigureParams {
+                    shadow_commits: true,
+                    ..Default::default()
+                })
+                .await;
+            (bridge, rx)
+        };
+
+        let (bridge, mut rx) = restarted().await;
+        bridge
+            .submit(chat(&dir, "first"), SessionTarget::New, None)
+            .await;
+        until_done(&mut rx).await;
+        // The shadow commit is made after `done`, before the slot frees.
+        while bridge.active.lock().await.is_some() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let (bridge, mut rx) = restarted().await;
+        bridge
+            .submit(
+                chat(&dir, "second"),
+                SessionTarget::Resume("s1".into()),
+                None,
+            )
+            .await;
+        until_done(&mut rx).await;
+        while bridge.active.lock().await.is_some() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let turns: Vec<(String, u32)> = shadow::log(&work)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.session_id, e.turn))
+            .collect();
+        assert_eq!(turns, vec![("s1".into(), 1), ("s1".into(), 2)]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_isolated_turn_reads_context_from_worktree() {
+        let dir = stub_cli("isolated-context", &format!("{}\n{}", INIT, RESULT));
+        let work = dir.join("work");
+        let git = |args: &'static [&'static str]| crate::git::git(&work, args);
+        git(&["init", "-q"]).await.unwrap();
+        git(&["config", "user.name", "t"]).await.unwrap();
+        git(&["config", "user.email", "t@t"]).await.unwrap();
+        std::fs::write(work.join("a.txt"), "one\n").unwrap();
+        git(&["add", "a.txt"]).await.unwrap();
+        git(&["commit", "-q", "-m", "add a"]).await.unwrap();
+        let wt = Worktree::create_in(&work, &dir.join("worktrees"))
+            .await
+            .unwrap();
+        std::fs::write(work.join("a.txt"), "local edit\n").unwrap();
+        std::fs::write(wt.path.join("a.txt"), "agent edit\n").unwrap();
+
+        let (bridge, mut rx) = bridge();
+        *bridge.worktree.lock().await = Some(wt.clone());
+        let params = ChatParams {
+            isolation: Some(Isolation::Worktree),
+            context_providers: vec![ContextRequest {
+                provider: ContextProvider::Diff { staged: false },
+                max_bytes: None,
+            }],
+            ..chat(&dir, "review")
+        };
+        bridge.submit(params, SessionTarget::New, None).await;
+        until_done(&mut rx).await;
+        let sent = lines(&dir.join("prompts")).join("\n");
+        assert!(sent.contains("agent edit"), "{}", sent);
+        assert!(!sent.contains("local edit"), "{}", sent);
+        wt.discard().await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_recovers_worktree_left_by_earlier_bridge() {
+        let dir = stub_cli("recover-worktree", INIT);
+        let work = dir.join("work");
+        let git = |args: &'static [&'static str]| crate::git::git(&work, args);
+        git(&["init", "-q"]).await.unwrap();
+        git(&["config", "user.name", "t"]).await.unwrap();
+        git(&["config", "user.email", "t@t"]).await.unwrap();
+        std::fs::write(work.join("a.txt"), "one\n").unwrap();
+        git(&["add", "a.txt"]).await.unwrap();
+        git(&["commit", "-q", "-m", "add a"]).await.unwrap();
+        let wt = Worktree::create_in(&work, &dir.join("worktrees"))
+            .await
+            .unwrap();
+
+        let (bridge, mut rx) = bridge();
+        bridge.recover_worktree(&work).await;
+        let events = until(&mut rx, |e| matches!(e, Event::Worktree { .. })).await;
+        match events.last() {
+            Some(Event::Worktree { path, state, .. }) => {
+                assert_eq!(*state, WorktreeState::Restored);
+                assert_eq!(*path, wt.path.display().to_string());
+            }
+            other => panic!("expected a worktree event, got {:?}", other),
+        }
+
+        bridge.close_worktree(false, None).await;
+        let events = until(&mut rx, |e| matches!(e, Event::Worktree { .. })).await;
+        assert!(matches!(
+            events.last(),
+            Some(Event::Worktree {
+                state: WorktreeState::Discarded,
+                ..
+            })
+        ));
+        assert!(!wt.path.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_watchdog_idle_stands_still_during_tools() {
+        let mut watchdog = Watchdog::new(TimeoutPolicy {
+            stall_warning_ms: None,
+            idle_timeout_ms: Some(10),
+            turn_timeout_ms: None,
+        });
+        watchdog.tool_started("t1");
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(matches!(watchdog.check(), WatchdogAction::Wait));
+        watchdog.tool_finished(Some("t1"));
+        assert!(matches!(watchdog.check(), WatchdogAction::TimedOut(_)));
+    }
+
+    #[test]
+    fn test_watchdog_turn_limit_spans_attempts() {
+        let mut watchdog = Watchdog::new(TimeoutPolicy {
+            stall_warning_ms: None,
+            idle_timeout_ms: None,
+            turn_timeout_ms: Some(10),
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        watchdog.attempt_started();
+        assert!(matches!(watchdog.check(), WatchdogAction::TimedOut(_)));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::edits::{content_hash, Proposal};
+use crate::error::{BridgeError, ErrorCode};
+use crate::merge::merge3;
+use crate::protocol::{BufferStateParams, ConflictReason, Event};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// How many recent proposals are kept for `merge` requests.
+const MAX_PROPOSALS: usize = 32;
+
+/// Buffer state as last reported by the editor.
+struct EditorBuffer {
+    content_hash: String,
+    modified: bool,
+}
+
+/// An edit Claude proposed, kept so the editor can merge it later.
+struct ProposedEdit {
+    tool_id: String,
+    path: PathBuf,
+    base: String,
+    content: String,
+}
+
+/// What the editor has open and which version of each file Claude last
+/// saw, so an edit based on a stale read can be flagged before it lands.
+#[derive(Clone)]
+pub struct BufferTracker {
+    editor: Arc<Mutex<HashMap<PathBuf, EditorBuffer>>>,
+    /// Path -> hash of the contents Claude last read or wrote.
+    seen: Arc<Mutex<HashMap<PathBuf, String>>>,
+    proposals: Arc<Mutex<VecDeque<ProposedEdit>>>,
+}
+
+impl BufferTracker {
+    pub fn new() -> Self {
+        Self {
+            editor: Arc::new(Mutex::new(HashMap::new())),
+            seen: Arc::new(Mutex::new(HashMap::new())),
+            proposals: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Records a `buffer_state` push. A missing hash means the buffer closed.
+    pub async fn update(&self, params: BufferStateParams) {
+        let mut editor = self.editor.lock().await;
+        let path = PathBuf::from(params.path);
+        match params.content_hash {
+            Some(content_hash) => {
+                editor.insert(
+                    path,
+                    EditorBuffer {
+                        content_hash,
+                        modified: params.modified,
+                    },
+                );
+            }
+            None => {
+                editor.remove(&path);
+            }
+        }
+    }
+
+    /// Notes that Claude now knows `path` as it is on disk.
+    pub async fn read(&self, path: &Path) {
+        if let Ok(bytes) = tokio::fs::read(path).await {
+            self.saw(path, content_hash(&bytes)).await;
+        }
+    }
+
+    /// Notes that Claude now knows `path` with contents hashing to `hash`.
+    pub async fn saw(&self, path: &Path, hash: String) {
+        self.seen.lock().await.insert(path.to_path_buf(), hash);
+    }
+
+    /// Notes a proposed edit: Claude now knows the file as edited, and the
+    /// editor may ask to merge it with its buffer.
+    pub async fn proposed(&self, tool_id: &str, path: &Path, proposal: &Proposal) {
+        self.saw(path, content_hash(proposal.content.as_bytes()))
+            .await;
+        let mut proposals = self.proposals.lock().await;
+        if proposals.len() >= MAX_PROPOSALS {
+            proposals.pop_front();
+        }
+        proposals.push_back(ProposedEdit {
+            tool_id: tool_id.to_string(),
+            path: path.to_path_buf(),
+            base: proposal.base.clone(),
+            content: proposal.content.clone(),
+        });
+    }
+
+    /// Three-way merges the editor's `buffer` with the edit proposed by
+    /// `tool_id`, against the file as Claude read it.
+    pub async fn merge(&self, tool_id: &str, buffer: &str) -> Result<Event, BridgeError> {
+        let proposals = self.proposals.lock().await;
+        let Some(edit) = proposals.iter().find(|p| p.tool_id == tool_id) else {
+            return Err(BridgeError::new(
+                ErrorCode::InvalidRequest,
+                format!("No proposed change for tool call {}", tool_id),
+            ));
+        };
+        let merged = merge3(&edit.base, buffer, &edit.content);
+        Ok(Event::MergeResult {
+            tool_id: tool_id.to_string(),
+            path: edit.path.display().to_string(),
+            content: merged.content,
+            conflicts: merged.conflicts,
+        })
+    }
+
+    /// A `conflict` event when the editor holds `path` in a state Claude
+    /// hasn't seen: unsaved changes, or a saved version newer than Claude's
+    /// last read.
+    pub async fn check(&self, tool_id: &str, path: &Path) -> Option<Event> {
+        let (buffer_hash, modified) = {
+            let editor = self.editor.lock().await;
+            let buffer = editor.get(path)?;
+            (buffer.content_hash.clone(), buffer.modified)
+        };
+        let read_hash = self.seen.lock().await.get(path).cloned();
+
+        let reason = if modified {
+            ConflictReason::Unsaved
+        } else if read_hash.as_ref().is_some_and(|h| *h != buffer_hash) {
+            // A buffer that merely hasn't reloaded Claude's own write yet
+            // differs from disk; only a saved user change matches it.
+            let on_disk = tokio::fs::read(path).await.ok()?;
+            if content_hash(&on_disk) != buffer_hash {
+                return None;
+            }
+            ConflictReason::StaleRead
+        } else {
+            return None;
+        };
+        Some(Event::Conflict {
+            tool_id: tool_id.to_string(),
+            path: path.display().to_string(),
+            reason,
+            buffer_hash,
+            read_hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(path: &Path, content: &str, modified: bool) -> BufferStateParams {
+        BufferStateParams {
+            path: path.display().to_string(),
+            content_hash: Some(content_hash(content.as_bytes())),
+            modified,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_flags_unsaved_and_stale_reads() {
+        let dir = std::env::temp_dir().join(format!("claudecode-buffers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "v1\n").unwrap();
+
+        let tracker = BufferTracker::new();
+        tracker.read(&file).await;
+        assert!(tracker.check("t1", &file).await.is_none());
+
+        tracker.update(state(&file, "v1\n", false)).await;
+        assert!(tracker.check("t1", &file).await.is_none());
+
+        tracker.update(state(&file, "v1 typing\n", true)).await;
+        match tracker.check("t1", &file).await {
+            Some(Event::Conflict { reason, .. }) => assert_eq!(reason, ConflictReason::Unsaved),
+            other => panic!("Expected Conflict, got {:?}", other),
+        }
+
+        std::fs::write(&file, "v2\n").unwrap();
+        tracker.update(state(&file, "v2\n", false)).await;
+        match tracker.check("t1", &file).await {
+            Some(Event::Conflict { reason, .. }) => assert_eq!(reason, ConflictReason::StaleRead),
+            other => panic!("Expected Conflict, got {:?}", other),
+        }
+
+        tracker.read(&file).await;
+        assert!(tracker.check("t1", &file).await.is_none());
+
+        tracker
+            .update(BufferStateParams {
+                path: file.display().to_string(),
+                content_hash: None,
+                modified: false,
+            })
+            .await;
+        assert!(tracker.check("t1", &file).await.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
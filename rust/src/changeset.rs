@@ -0,0 +1,238 @@
+use crate::edits::unified_diff;
+use crate::protocol::{ChangeKind, Event, FileChange};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Files beyond this many are not tracked.
+const MAX_FILES: usize = 20_000;
+/// Larger files are tracked by size and mtime only, without a diff.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// Total contents kept in memory for one snapshot.
+const MAX_TOTAL_BYTES: usize = 64 * 1024 * 1024;
+/// Longest combined diff sent in a `changeset` event.
+const MAX_DIFF_BYTES: usize = 1024 * 1024;
+
+type Contents = Option<Vec<u8>>;
+
+struct Entry {
+    len: u64,
+    modified: Option<SystemTime>,
+    /// `None` when the file was too large or the budget ran out.
+    content: Contents,
+}
+
+/// The working tree as a turn found it, so whatever changed on disk by the
+/// end of the turn can be reported, whichever tool changed it.
+pub struct TreeSnapshot {
+    root: PathBuf,
+    files: HashMap<PathBuf, Entry>,
+    /// False when the file limit cut the walk short; new files then cannot
+    /// be told apart from untracked ones.
+    complete: bool,
+}
+
+/// Files under `root` that aren't ignored by .gitignore and friends.
+pub fn walk(root: &Path) -> ignore::Walk {
+    ignore::WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build()
+}
+
+impl TreeSnapshot {
+    /// Walks `root`. Blocking; run it off the async workers.
+    pub fn capture(root: &Path) -> Self {
+        let mut files = HashMap::new();
+        let mut budget = MAX_TOTAL_BYTES;
+        let mut complete = true;
+        for entry in walk(root).flatten() {
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            if files.len() >= MAX_FILES {
+                complete = false;
+                break;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let content = if meta.len() <= MAX_FILE_BYTES && (meta.len() as usize) <= budget {
+                std::fs::read(entry.path()).ok()
+            } else {
+                None
+            };
+            budget -= content.as_ref().map_or(0, |c| c.len()).min(budget);
+            let Ok(rel) = entry.path().strip_prefix(root) else {
+                continue;
+            };
+            files.insert(
+                rel.to_path_buf(),
+                Entry {
+                    len: meta.len(),
+                    modified: meta.modified().ok(),
+                    content,
+                },
+            );
+        }
+        Self {
+            root: root.to_path_buf(),
+            files,
+            complete,
+        }
+    }
+
+    /// Compares the snapshot with the disk now. Blocking.
+    pub fn changeset(&self) -> Event {
+        let mut changes: Vec<(PathBuf, ChangeKind, Contents, Contents)> = Vec::new();
+
+        for (rel, entry) in &self.files {
+            let path = self.root.join(rel);
+            let Ok(meta) = std::fs::metadata(&path) else {
+                changes.push((
+                    rel.clone(),
+                    ChangeKind::Deleted,
+                    entry.content.clone(),
+                    None,
+                ));
+                continue;
+            };
+            if meta.len() == entry.len && meta.modified().ok() == entry.modified {
+                continue;
+            }
+            let now = std::fs::read(&path).ok();
+            if entry.content.is_some() && now == entry.content {
+                continue;
+            }
+            changes.push((
+                rel.clone(),
+                ChangeKind::Modified,
+                entry.content.clone(),
+                now,
+            ));
+        }
+
+        if self.complete {
+            for entry in walk(&self.root).flatten() {
+                if !entry.file_type().is_some_and(|t| t.is_file()) {
+                    continue;
+                }
+                let Ok(rel) = entry.path().strip_prefix(&self.root) else {
+                    continue;
+                };
+                if !self.files.contains_key(rel) {
+                    let now = std::fs::read(entry.path()).ok();
+                    changes.push((rel.to_path_buf(), ChangeKind::Created, None, now));
+                }
+            }
+        }
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut files = Vec::new();
+        let mut diff = String::new();
+        let mut truncated = !self.complete;
+        for (rel, kind, old, new) in changes {
+            let name = rel.display().to_string();
+            let (text, added, deleted) = file_diff(&name, kind, old.as_deref(), new.as_deref());
+            if diff.len() + text.len() <= MAX_DIFF_BYTES {
+                diff.push_str(&text);
+            } else {
+                truncated = true;
+            }
+            files.push(FileChange {
+                path: name,
+                kind,
+                added,
+                deleted,
+            });
+        }
+        Event::Changeset {
+            added: files.iter().map(|f| f.added).sum(),
+            deleted: files.iter().map(|f| f.deleted).sum(),
+            files,
+            diff,
+            truncated,
+        }
+    }
+}
+
+/// The diff text for one file plus its added and deleted line counts.
+/// Contents that weren't kept or aren't text get a one-line note instead.
+fn file_diff(
+    name: &str,
+    kind: ChangeKind,
+    old: Option<&[u8]>,
+    new: Option<&[u8]>,
+) -> (String, usize, usize) {
+    let as_text = |bytes: Option<&[u8]>, absent: bool| match bytes {
+        Some(b) => std::str::from_utf8(b).ok().map(str::to_string),
+        None if absent => Some(String::new()),
+        None => None,
+    };
+    let old_text = as_text(old, kind == ChangeKind::Created);
+    let new_text = as_text(new, kind == ChangeKind::Deleted);
+    let (Some(old_text), Some(new_text)) = (old_text, new_text) else {
+        return (format!("Binary or large file {} changed\n", name), 0, 0);
+    };
+
+    let (text, hunks) = unified_diff(&old_text, &new_text, name);
+    let count = |sign: char| {
+        hunks
+            .iter()
+            .flat_map(|h| &h.lines)
+            .filter(|l| l.starts_with(sign))
+            .count()
+    };
+    (text, count('+'), count('-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changeset_sees_created_modified_deleted_and_ignores() {
+        let root =
+            std::env::temp_dir().join(format!("claudecode-changeset-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("build")).unwrap();
+        std::fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        std::fs::write(root.join("keep.txt"), "same\n").unwrap();
+        std::fs::write(root.join("edit.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(root.join("gone.txt"), "bye\n").unwrap();
+
+        let snapshot = TreeSnapshot::capture(&root);
+        std::fs::write(root.join("edit.txt"), "one\n2\nthree\n").unwrap();
+        std::fs::remove_file(root.join("gone.txt")).unwrap();
+        std::fs::write(root.join("new.txt"), "hi\n").unwrap();
+        std::fs::write(root.join("build/out.o"), "ignored").unwrap();
+
+        let Event::Changeset {
+            files,
+            added,
+            deleted,
+            diff,
+            truncated,
+        } = snapshot.changeset()
+        else {
+            panic!("Expected Changeset");
+        };
+        let summary: Vec<(&str, ChangeKind, usize, usize)> = files
+            .iter()
+            .map(|f| (f.path.as_str(), f.kind, f.added, f.deleted))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("edit.txt", ChangeKind::Modified, 2, 1),
+                ("gone.txt", ChangeKind::Deleted, 0, 1),
+                ("new.txt", ChangeKind::Created, 1, 0),
+            ]
+        );
+        assert_eq!((added, deleted), (3, 2));
+        assert!(diff.contains("+++ b/new.txt"));
+        assert!(!truncated);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
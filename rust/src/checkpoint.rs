@@ -0,0 +1,548 @@
+use crate::claude::unix_now;
+use crate::edits::content_hash;
+use crate::error::{BridgeError, ErrorCode};
+use crate::hook::PreImage;
+use crate::protocol::CheckpointInfo;
+use crate::usage::state_dir;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Longest prompt excerpt kept with a checkpoint.
+const PROMPT_EXCERPT: usize = 200;
+
+/// A file as it was before the turn first touched it, and as the turn
+/// left it. `None` hashes mean the file did not exist.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileSnapshot {
+    path: String,
+    before: Option<String>,
+    after: Option<String>,
+    /// Whether `after` was recorded; an interrupted turn never seals.
+    sealed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Checkpoint {
+    turn: u32,
+    created_at: u64,
+    prompt: String,
+    files: Vec<FileSnapshot>,
+}
+
+/// What `rollback` did.
+pub struct RollbackReport {
+    pub restored: Vec<String>,
+    pub conflicts: Vec<String>,
+}
+
+/// Snapshots of files modified by tool calls, so a whole turn can be
+/// undone. Contents live in a content-addressed object store; each session
+/// has an index of its checkpoints, numbered by turn from 1. Turns that
+/// touched no files keep an empty entry, so the index also numbers the
+/// session's turns for the shadow log.
+#[derive(Clone)]
+pub struct CheckpointStore {
+    dir: Option<PathBuf>,
+    /// Bridge turn id -> (session id, checkpoint turn) for turns in flight.
+    open: Arc<Mutex<HashMap<u64, (String, u32)>>>,
+}
+
+impl CheckpointStore {
+    pub fn new() -> Self {
+        Self::at(state_dir().map(|d| d.join("checkpoints")))
+    }
+
+    /// A store under `dir`; `None` keeps no checkpoints.
+    pub fn at(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            open: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Saves `before` as the contents of `path` before this turn, unless
+    /// the turn already saved it.
+    pub async fn snapshot(
+        &self,
+        bridge_turn: u64,
+        session_id: &str,
+        prompt: &str,
+        path: &Path,
+        before: PreImage,
+    ) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        let open = match self.open.lock().await.get(&bridge_turn) {
+            Some((sid, turn)) if sid == session_id => Some(*turn),
+            _ => None,
+        };
+        let session = session_id.to_string();
+        let prompt: String = prompt.chars().take(PROMPT_EXCERPT).collect();
+        let key = path.display().to_string();
+        let saved = tokio::task::spawn_blocking(move || {
+            record_before(&dir, &session, open, prompt, key, before)
+        })
+        .await;
+        if let (None, Ok(Some(turn))) = (open, saved) {
+            self.open
+                .lock()
+                .await
+                .insert(bridge_turn, (session_id.to_string(), turn));
+        }
+    }
+
+    /// The session turn number of `bridge_turn`, taking the next one when
+    /// the turn saved no files. Call before `seal`.
+    pub async fn number(&self, bridge_turn: u64, session_id: &str, prompt: &str) -> Option<u32> {
+        let dir = self.dir.clone()?;
+        if let Some((sid, turn)) = self.open.lock().await.get(&bridge_turn) {
+            if sid == session_id {
+                return Some(*turn);
+            }
+        }
+        let session_id = session_id.to_string();
+        let prompt: String = prompt.chars().take(PROMPT_EXCERPT).collect();
+        tokio::task::spawn_blocking(move || {
+            let mut index = load_index(&dir, &session_id);
+            let turn = index.last().map_or(1, |c| c.turn + 1);
+            index.push(Checkpoint {
+                turn,
+                created_at: unix_now(),
+                prompt,
+                files: Vec::new(),
+            });
+            save_index(&dir, &session_id, &index);
+            turn
+        })
+        .await
+        .ok()
+    }
+
+    /// Records how the turn left each file it touched.
+    pub async fn seal(&self, bridge_turn: u64) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        let Some((session_id, turn)) = self.open.lock().await.remove(&bridge_turn) else {
+            return;
+        };
+        let _ = tokio::task::spawn_blocking(move || {
+            let mut index = load_index(&dir, &session_id);
+            let Some(checkpoint) = index.iter_mut().find(|c| c.turn == turn) else {
+                return;
+            };
+            for file in &mut checkpoint.files {
+                file.after = current_hash(Path::new(&file.path));
+                file.sealed = true;
+            }
+            save_index(&dir, &session_id, &index);
+        })
+        .await;
+    }
+
+    pub async fn list(&self, session_id: &str) -> Vec<CheckpointInfo> {
+        let Some(dir) = self.dir.clone() else {
+            return Vec::new();
+        };
+        let session_id = session_id.to_string();
+        let index = tokio::task::spawn_blocking(move || load_index(&dir, &session_id))
+            .await
+            .unwrap_or_default();
+        index
+            .into_iter()
+            .filter(|c| !c.files.is_empty())
+            .map(|c| CheckpointInfo {
+                turn: c.turn,
+                created_at: c.created_at,
+                prompt: c.prompt,
+                files: c.files.into_iter().map(|f| f.path).collect(),
+            })
+            .collect()
+    }
+
+    /// Restores every file touched in `turn` and later turns to how it was
+    /// before `turn`. Files changed since a turn last wrote them are left
+    /// alone and reported unless `force` is set; the checkpoints are only
+    /// dropped once nothing conflicts. Not to be run alongside a turn.
+    pub async fn rollback(
+        &self,
+        session_id: &str,
+        turn: u32,
+        force: bool,
+    ) -> Result<RollbackReport, BridgeError> {
+        let Some(dir) = self.dir.clone() else {
+            return Err(BridgeError::new(
+                ErrorCode::InvalidRequest,
+                "No state directory for checkpoints",
+            ));
+        };
+        let session_id = session_id.to_string();
+        tokio::task::spawn_blocking(move || rollback(&dir, &session_id, turn, force))
+            .await
+            .map_err(|e| BridgeError::new(ErrorCode::InvalidRequest, e.to_string()))?
+    }
+
+    /// Forgets every checkpoint of `session_id` and frees the contents no
+    /// other session refers to. Turn numbers are kept. Not to be run
+    /// alongside a turn.
+    pub async fn delete(&self, session_id: &str) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        let session_id = session_id.to_string();
+        let _ = tokio::task::spawn_blocking(move || {
+            let mut index = load_index(&dir, &session_id);
+            if index.is_empty() {
+                return;
+            }
+            for checkpoint in &mut index {
+                checkpoint.files.clear();
+            }
+            save_index(&dir, &session_id, &index);
+            prune(&dir);
+        })
+        .await;
+    }
+}
+
+/// Adds `path` to the checkpoint for `turn`, or to a new checkpoint when
+/// the turn has none yet. Returns the checkpoint's turn once it is saved.
+fn record_before(
+    dir: &Path,
+    session_id: &str,
+    turn: Option<u32>,
+    prompt: String,
+    path: String,
+    before: PreImage,
+) -> Option<u32> {
+    let mut index = load_index(dir, session_id);
+    let turn = match turn {
+        Some(turn) => turn,
+        None => {
+            let turn = index.last().map_or(1, |c| c.turn + 1);
+            index.push(Checkpoint {
+                turn,
+                created_at: unix_now(),
+                prompt,
+                files: Vec::new(),
+            });
+            turn
+        }
+    };
+    let checkpoint = index.iter_mut().find(|c| c.turn == turn)?;
+    if checkpoint.files.iter().any(|f| f.path == path) {
+        return Some(turn);
+    }
+    let before = match before {
+        Some(bytes) => Some(store_object(dir, &bytes)?),
+        None => None,
+    };
+    checkpoint.files.push(FileSnapshot {
+        path,
+        before,
+        after: None,
+        sealed: false,
+    });
+    save_index(dir, session_id, &index);
+    Some(turn)
+}
+
+fn rollback(
+    dir: &Path,
+    session_id: &str,
+    turn: u32,
+    force: bool,
+) -> Result<RollbackReport, BridgeError> {
+    let mut index = load_index(dir, session_id);
+    let Some(first) = index.iter().position(|c| c.turn == turn) else {
+        return Err(BridgeError::new(
+            ErrorCode::InvalidRequest,
+            format!("No checkpoint for turn {} of session {}", turn, session_id),
+        ));
+    };
+
+    // Each file's state before `turn`, and the last state a turn left.
+    let mut files: Vec<FileSnapshot> = Vec::new();
+    for f in index[first..].iter().flat_map(|c| &c.files) {
+        match files.iter_mut().find(|e| e.path == f.path) {
+            Some(e) => {
+                e.after = f.after.clone();
+                e.sealed = f.sealed;
+            }
+            None => files.push(f.clone()),
+        }
+    }
+
+    let mut report = RollbackReport {
+        restored: Vec::new(),
+        conflicts: Vec::new(),
+    };
+    for file in files {
+        let path = file.path;
+        let current = current_hash(Path::new(&path));
+        if current == file.before {
+            continue;
+        }
+        if file.sealed && current != file.after && !force {
+            report.conflicts.push(path);
+            continue;
+        }
+        match restore(dir, Path::new(&path), file.before.as_deref()) {
+            Ok(()) => report.restored.push(path),
+            Err(e) => {
+                return Err(BridgeError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("Failed to restore {}: {}", path, e),
+                ))
+            }
+        }
+    }
+
+    // Emptied rather than dropped: the session's turns stay numbered.
+    if report.conflicts.is_empty() {
+        for checkpoint in &mut index[first..] {
+            checkpoint.files.clear();
+        }
+        save_index(dir, session_id, &index);
+        prune(dir);
+    }
+    Ok(report)
+}
+
+/// Removes objects no session's checkpoints refer to.
+fn prune(dir: &Path) {
+    let Ok(sessions) = std::fs::read_dir(dir.join("sessions")) else {
+        return;
+    };
+    let mut live = HashSet::new();
+    for entry in sessions.flatten() {
+        let index: Vec<Checkpoint> = std::fs::read_to_string(entry.path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        for file in index.into_iter().flat_map(|c| c.files) {
+            live.extend(file.before);
+        }
+    }
+    let Ok(objects) = std::fs::read_dir(dir.join("objects")) else {
+        return;
+    };
+    for entry in objects.flatten() {
+        if !live.contains(entry.file_name().to_string_lossy().as_ref()) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn current_hash(path: &Path) -> Option<String> {
+    std::fs::read(path).ok().map(|bytes| content_hash(&bytes))
+}
+
+fn restore(dir: &Path, path: &Path, hash: Option<&str>) -> std::io::Result<()> {
+    match hash {
+        Some(hash) => {
+            let bytes = std::fs::read(dir.join("objects").join(hash))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, bytes)
+        }
+        None => match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
+fn store_object(dir: &Path, bytes: &[u8]) -> Option<String> {
+    let hash = content_hash(bytes);
+    let objects = dir.join("objects");
+    let path = objects.join(&hash);
+    if !path.exists() {
+        std::fs::create_dir_all(&objects).ok()?;
+        std::fs::write(&path, bytes).ok()?;
+    }
+    Some(hash)
+}
+
+fn index_path(dir: &Path, session_id: &str) -> PathBuf {
+    // Session ids are UUIDs; keep anything else from escaping the store.
+    let name: String = session_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    dir.join("sessions").join(format!("{}.json", name))
+}
+
+fn load_index(dir: &Path, session_id: &str) -> Vec<Checkpoint> {
+    std::fs::read_to_string(index_path(dir, session_id))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_index(dir: &Path, session_id: &str, index: &[Checkpoint]) {
+    let path = index_path(dir, session_id);
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    // Written aside and renamed so a reader never sees half an index.
+    if let Ok(json) = serde_json::to_string(index) {
+        let partial = path.with_extension("json.partial");
+        if std::fs::write(&partial, json).is_ok() {
+            let _ = std::fs::rename(&partial, &path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "claudecode-checkpoint-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_rollback_restores_and_deletes() {
+        let dir = scratch("restore");
+        let store = CheckpointStore::at(Some(dir.join("store")));
+        let edited = dir.join("a.txt");
+        let created = dir.join("new.txt");
+        std::fs::write(&edited, "before").unwrap();
+
+        store
+            .snapshot(1, "s1", "edit a", &edited, Some(b"before".to_vec()))
+            .await;
+        store.snapshot(1, "s1", "edit a", &created, None).await;
+        std::fs::write(&edited, "after").unwrap();
+        std::fs::write(&created, "new").unwrap();
+        store.seal(1).await;
+
+        assert_eq!(store.list("s1").await.len(), 1);
+        let report = store.rollback("s1", 1, false).await.unwrap();
+        assert_eq!(report.restored.len(), 2);
+        assert!(report.conflicts.is_empty());
+        assert_eq!(std::fs::read_to_string(&edited).unwrap(), "before");
+        assert!(!created.exists());
+        assert!(store.list("s1").await.is_empty());
+        // Nothing refers to the saved contents any more.
+        assert_eq!(
+            std::fs::read_dir(dir.join("store/objects"))
+                .unwrap()
+                .count(),
+            0
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_rollback_reports_user_edits_as_conflicts() {
+        let dir = scratch("conflict");
+        let store = CheckpointStore::at(Some(dir.join("store")));
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "v1").unwrap();
+
+        store
+            .snapshot(1, "s1", "first", &file, Some(b"v1".to_vec()))
+            .await;
+        std::fs::write(&file, "v2").unwrap();
+        store.seal(1).await;
+        store
+            .snapshot(2, "s1", "second", &file, Some(b"v2".to_vec()))
+            .await;
+        std::fs::write(&file, "v3").unwrap();
+        store.seal(2).await;
+        assert_eq!(store.list("s1").await[1].turn, 2);
+
+        std::fs::write(&file, "user edit").unwrap();
+        let report = store.rollback("s1", 1, false).await.unwrap();
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "user edit");
+        assert_eq!(store.list("s1").await.len(), 2);
+
+        let report = store.rollback("s1", 1, true).await.unwrap();
+        assert_eq!(report.restored.len(), 1);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "v1");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_delete_frees_only_unshared_contents() {
+        let dir = scratch("delete");
+        let store = CheckpointStore::at(Some(dir.join("store")));
+        let file = dir.join("a.txt");
+
+        store
+            .snapshot(1, "s1", "one", &file, Some(b"shared".to_vec()))
+            .await;
+        store
+            .snapshot(
+                1,
+                "s1",
+                "one",
+                &dir.join("b.txt"),
+                Some(b"only s1".to_vec()),
+            )
+            .await;
+        store.seal(1).await;
+        store
+            .snapshot(2, "s2", "two", &file, Some(b"shared".to_vec()))
+            .await;
+        store.seal(2).await;
+        let objects = || {
+            std::fs::read_dir(dir.join("store/objects"))
+                .unwrap()
+                .count()
+        };
+        assert_eq!(objects(), 2);
+
+        store.delete("s1").await;
+        assert!(store.list("s1").await.is_empty());
+        assert_eq!(store.list("s2").await.len(), 1);
+        assert_eq!(objects(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_turn_numbers_survive_restarts_and_rollbacks() {
+        let dir = scratch("number");
+        let file = dir.join("a.txt");
+        let store = CheckpointStore::at(Some(dir.join("store")));
+        assert_eq!(store.number(1, "s1", "ask").await, Some(1));
+        store
+            .snapshot(2, "s1", "edit", &file, Some(b"v1".to_vec()))
+            .await;
+        assert_eq!(store.number(2, "s1", "edit").await, Some(2));
+        store.seal(2).await;
+        // Only turns that saved files are listed.
+        let listed: Vec<u32> = store.list("s1").await.iter().map(|c| c.turn).collect();
+        assert_eq!(listed, vec![2]);
+
+        // A restarted bridge resuming the session carries on counting.
+        let store = CheckpointStore::at(Some(dir.join("store")));
+        assert_eq!(store.number(1, "s1", "again").await, Some(3));
+        store.rollback("s1", 2, true).await.unwrap();
+        assert!(store.list("s1").await.is_empty());
+        assert_eq!(store.number(2, "s1", "after").await, Some(4));
+        assert_eq!(store.number(3, "s2", "other").await, Some(1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,14 +1,142 @@
-use crate::protocol::{ChatParams, Event};
+use crate::error::{BridgeError, ErrorCode};
+use crate::protocol::{ChatParams, Event, LaunchOptions, ResourceLimits, Severity};
 use crate::session::SessionManager;
 use serde_json::Value;
-use std::process::Stdio;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{Child, Command};
-use tokio::sync::mpsc;
+use tokio::process::{ChildStdin, Command};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
 
+/// The CLI's stdin in stream-json input mode. It stays open while user
+/// messages are outstanding so more can be injected mid-turn; each message
+/// is answered by one `result`.
+struct TurnInput {
+    stdin: Option<ChildStdin>,
+    outstanding: usize,
+}
+
+impl TurnInput {
+    async fn send(&mut self, text: &str) -> Result<(), BridgeError> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| BridgeError::new(ErrorCode::Busy, "claude is not accepting input"))?;
+        stdin
+            .write_all(user_message_line(text).as_bytes())
+            .await
+            .map_err(|e| {
+                BridgeError::new(
+                    ErrorCode::SpawnFailed,
+                    format!("Failed to write to stdin: {}", e),
+                )
+            })?;
+        self.outstanding += 1;
+        Ok(())
+    }
+
+    /// Accounts for one `result`. Closes stdin and returns true once no
+    /// user messages are left unanswered.
+    fn answer_one(&mut self) -> bool {
+        self.outstanding = self.outstanding.saturating_sub(1);
+        if self.outstanding == 0 {
+            self.stdin = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type RlimitResource = libc::c_int;
+
+/// Runs in the forked child before exec; must stay async-signal-safe.
+fn apply_limits(limits: &ResourceLimits) -> std::io::Result<()> {
+    if let Some(secs) = limits.cpu_seconds {
+        set_rlimit(libc::RLIMIT_CPU, secs)?;
+    }
+    if let Some(mb) = limits.address_space_mb {
+        set_rlimit(libc::RLIMIT_AS, mb.saturating_mul(1024 * 1024))?;
+    }
+    if let Some(nice) = limits.nice {
+        // SAFETY: adjusts only this process's priority. -1 is a valid
+        // result, and a refused increase leaves the priority as it was.
+        unsafe {
+            libc::nice(nice);
+        }
+    }
+    Ok(())
+}
+
+/// Lowers the soft limit, keeping the hard limit so it cannot fail for
+/// lack of privilege.
+fn set_rlimit(resource: RlimitResource, value: u64) -> std::io::Result<()> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid, writable rlimit.
+    if unsafe { libc::getrlimit(resource, &mut limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    limit.rlim_cur = (value as libc::rlim_t).min(limit.rlim_max);
+    // SAFETY: `limit` is a valid rlimit.
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn user_message_line(text: &str) -> String {
+    let msg = serde_json::json!({
+        "type": "user",
+        "message": {
+            "role": "user",
+            "content": [{ "type": "text", "text": text }],
+        },
+    });
+    format!("{}\n", msg)
+}
+
+pub fn command(launch: &LaunchOptions) -> Command {
+    let mut cmd = Command::new(launch.claude_path.as_deref().unwrap_or("claude"));
+    if let Some(ref names) = launch.env_passthrough {
+        cmd.env_clear();
+        for name in names {
+            if let Some(value) = std::env::var_os(name) {
+                cmd.env(name, value);
+            }
+        }
+    }
+    for (name, value) in &launch.env {
+        match value {
+            Some(value) => cmd.env(name, value),
+            None => cmd.env_remove(name),
+        };
+    }
+    cmd
+}
+
+/// A running CLI. Clones share the process, so one can be waited on or
+/// signalled without holding whatever owns it; the CLI is killed once the
+/// last clone is dropped.
+#[derive(Clone)]
 pub struct ClaudeProcess {
-    child: Child,
+    pid: Option<u32>,
+    /// The CLI leads its own process group, so tools it starts can be
+    /// signalled along with it.
+    pgid: Option<libc::pid_t>,
     session: SessionManager,
+    input: Arc<Mutex<TurnInput>>,
+    exit: watch::Receiver<Option<Result<ExitStatus, String>>>,
+    _alive: Arc<oneshot::Sender<()>>,
 }
 
 impl ClaudeProcess {
@@ -16,10 +144,13 @@ impl ClaudeProcess {
         params: &ChatParams,
         resume_session: Option<String>,
         session: SessionManager,
+        request_id: Option<String>,
         event_tx: mpsc::UnboundedSender<Event>,
-    ) -> Result<Self, String> {
-        let mut cmd = Command::new("claude");
+        settings: Option<&Path>,
+    ) -> Result<Self, BridgeError> {
+        let mut cmd = command(&params.launch);
         cmd.arg("-p");
+        cmd.arg("--input-format").arg("stream-json");
         cmd.arg("--output-format").arg("stream-json");
         cmd.arg("--verbose");
         cmd.arg("--include-partial-messages");
@@ -36,6 +167,10 @@ impl ClaudeProcess {
             cmd.arg("--allowed-tools").arg(tools.join(","));
         }
 
+        if let Some(ref mcp_config) = params.mcp_config {
+            cmd.arg("--mcp-config").arg(mcp_config);
+        }
+
         if let Some(ref prompt) = params.append_system_prompt {
             cmd.arg("--append-system-prompt").arg(prompt);
         }
@@ -48,15 +183,38 @@ impl ClaudeProcess {
             cmd.arg("--resume").arg(sid);
         }
 
+        if let Some(settings) = settings {
+            cmd.arg("--settings").arg(settings);
+        }
+
         cmd.stdin(Stdio::piped());
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
+        // A process dropped with its turn (e.g. an aborted task) must not
+        // outlive the bridge.
+        cmd.kill_on_drop(true);
+        cmd.process_group(0);
+        if let Some(limits) = params.limits.clone() {
+            // SAFETY: the hook only makes async-signal-safe libc calls.
+            unsafe {
+                cmd.pre_exec(move || apply_limits(&limits));
+            }
+        }
 
-        let mut child = cmd.spawn().map_err(|e| format!("Failed to spawn claude: {}", e))?;
+        let mut child = cmd.spawn().map_err(|e| {
+            let code = if e.kind() == ErrorKind::NotFound {
+                ErrorCode::CliNotFound
+            } else {
+                ErrorCode::SpawnFailed
+            };
+            BridgeError::new(code, format!("Failed to spawn claude: {}", e))
+        })?;
 
-        let stdout = child.stdout.take().ok_or("No stdout")?;
-        let stderr = child.stderr.take().ok_or("No stderr")?;
-        let mut stdin = child.stdin.take().ok_or("No stdin")?;
+        let missing_pipe =
+            |name: &str| BridgeError::new(ErrorCode::SpawnFailed, format!("No {}", name));
+        let stdout = child.stdout.take().ok_or_else(|| missing_pipe("stdout"))?;
+        let stderr = child.stderr.take().ok_or_else(|| missing_pipe("stderr"))?;
+        let stdin = child.stdin.take().ok_or_else(|| missing_pipe("stdin"))?;
 
         let prompt_text = if let Some(ref ctx) = params.context {
             format!("{}\n\n{}", ctx, params.prompt)
@@ -64,32 +222,62 @@ impl ClaudeProcess {
             params.prompt.clone()
         };
 
-        if !prompt_text.is_empty() {
-            stdin
-                .write_all(prompt_text.as_bytes())
-                .await
-                .map_err(|e| format!("Failed to write to stdin: {}", e))?;
+        let mut input = TurnInput {
+            stdin: Some(stdin),
+            outstanding: 0,
+        };
+        if prompt_text.is_empty() {
+            input.stdin = None;
+        } else {
+            input.send(&prompt_text).await?;
         }
-        drop(stdin);
+        let input = Arc::new(Mutex::new(input));
 
         let err_tx = event_tx.clone();
+        let err_request_id = request_id.clone();
         tokio::spawn(async move {
             let reader = BufReader::new(stderr);
             let mut lines = reader.lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                if !line.is_empty() {
-                    let _ = err_tx.send(Event::Error {
-                        message: format!("[stderr] {}", line),
-                    });
+            let mut classifier = StderrClassifier::default();
+            loop {
+                // A held error is sent once no more trace lines follow
+                // shortly, rather than waiting for the next line or EOF.
+                let next = if classifier.pending() {
+                    match tokio::time::timeout(TRACE_FLUSH, lines.next_line()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            if let Some(entry) = classifier.finish() {
+                                entry.send(&err_tx, err_request_id.clone());
+                            }
+                            continue;
+                        }
+                    }
+                } else {
+                    lines.next_line().await
+                };
+                let Ok(Some(line)) = next else {
+                    break;
+                };
+                for entry in classifier.push(&line) {
+                    entry.send(&err_tx, err_request_id.clone());
                 }
             }
+            if let Some(entry) = classifier.finish() {
+                entry.send(&err_tx, err_request_id);
+            }
         });
 
         let sess = session.clone();
+        let stdout_input = input.clone();
         tokio::spawn(async move {
             let reader = BufReader::new(stdout);
             let mut lines = reader.lines();
             let mut accumulated_text = String::new();
+            // Output tokens of finished messages, and of the one streaming.
+            let mut settled_tokens = 0u64;
+            let mut message_tokens = 0u64;
+            // The CLI reports cost cumulatively for the process.
+            let mut reported_cost = 0.0f64;
 
             while let Ok(Some(line)) = lines.next_line().await {
                 if line.is_empty() {
@@ -162,14 +350,27 @@ impl ClaudeProcess {
                                         }
                                     }
                                 }
-                                "content_block_stop" => {
-                                    if !accumulated_text.is_empty() {
-                                        let _ = event_tx.send(Event::Text {
-                                            text: accumulated_text.clone(),
-                                        });
-                                        accumulated_text.clear();
+                                "message_start" => {
+                                    settled_tokens += message_tokens;
+                                    message_tokens = 0;
+                                }
+                                "message_delta" => {
+                                    if let Some(tokens) = evt
+                                        .get("usage")
+                                        .and_then(|u| u.get("output_tokens"))
+                                        .and_then(|v| v.as_u64())
+                                    {
+                                        message_tokens = tokens;
+                                        sess.set_output_tokens(settled_tokens + message_tokens)
+                                            .await;
                                     }
                                 }
+                                "content_block_stop" if !accumulated_text.is_empty() => {
+                                    let _ = event_tx.send(Event::Text {
+                                        text: accumulated_text.clone(),
+                                    });
+                                    accumulated_text.clear();
+                                }
                                 _ => {}
                             }
                         }
@@ -257,6 +458,9 @@ impl ClaudeProcess {
                             .and_then(|v| v.as_u64())
                             .unwrap_or(0);
 
+                        sess.add_cost((total_cost - reported_cost).max(0.0)).await;
+                        reported_cost = reported_cost.max(total_cost);
+
                         let _ = event_tx.send(Event::Cost {
                             total_usd: total_cost,
                             duration_ms: duration,
@@ -274,11 +478,36 @@ impl ClaudeProcess {
                                 .and_then(|v| v.as_str())
                                 .unwrap_or("Unknown error")
                                 .to_string();
-                            let _ = event_tx.send(Event::Error { message: msg });
+                            let code = parsed
+                                .get("api_error_status")
+                                .and_then(|v| v.as_u64())
+                                .and_then(ErrorCode::from_status)
+                                .unwrap_or_else(|| ErrorCode::from_cli_message(&msg));
+                            let mut err = BridgeError::new(code, msg);
+                            if code == ErrorCode::RateLimited {
+                                let reset_at = parse_reset_at(&err.message);
+                                err.retry_after = reset_at.map(|at| at.saturating_sub(unix_now()));
+                                let _ = event_tx.send(Event::UsageLimit {
+                                    reset_at,
+                                    limited: true,
+                                });
+                            }
+                            let _ = event_tx.send(err.into_event(request_id.clone()));
                         }
 
-                        sess.set_inactive().await;
-                        let _ = event_tx.send(Event::Done);
+                        let finished = {
+                            let mut input = stdout_input.lock().await;
+                            if is_error {
+                                input.stdin = None;
+                                true
+                            } else {
+                                input.answer_one()
+                            }
+                        };
+                        if finished {
+                            sess.set_inactive().await;
+                            let _ = event_tx.send(Event::Done);
+                        }
                     }
                     _ => {}
                 }
@@ -287,23 +516,373 @@ impl ClaudeProcess {
             sess.set_inactive().await;
         });
 
-        Ok(Self { child, session })
+        let pid = child.id();
+        let (exit_tx, exit) = watch::channel(None);
+        let (alive, dropped) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = dropped => {
+                    let _ = child.start_kill();
+                    child.wait().await
+                }
+            };
+            let _ = exit_tx.send(Some(
+                status.map_err(|e| format!("Claude process error: {}", e)),
+            ));
+        });
+
+        Ok(Self {
+            pid,
+            pgid: pid.map(|pid| pid as libc::pid_t),
+            session,
+            input,
+            exit,
+            _alive: Arc::new(alive),
+        })
     }
 
-    pub async fn abort(&mut self) -> Result<(), String> {
-        self.child
-            .kill()
-            .await
-            .map_err(|e| format!("Failed to kill claude process: {}", e))?;
+    /// Injects a user message into the running conversation. Fails once the
+    /// CLI has answered everything and its stdin is closed.
+    pub async fn send_user_message(&self, text: &str) -> Result<(), BridgeError> {
+        self.input.lock().await.send(text).await
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    pub async fn abort(&self) -> Result<(), String> {
+        if !self.exited() {
+            self.signal_group(libc::SIGKILL);
+        }
+        self.wait().await?;
         self.session.set_inactive().await;
         Ok(())
     }
 
-    pub async fn wait(&mut self) -> Result<(), String> {
-        self.child
-            .wait()
-            .await
-            .map_err(|e| format!("Claude process error: {}", e))?;
+    pub async fn terminate(&self, grace: Duration) -> Result<(), String> {
+        self.input.lock().await.stdin = None;
+        if !self.exited() {
+            self.signal_group(libc::SIGTERM);
+        }
+        if tokio::time::timeout(grace, self.wait()).await.is_err() {
+            return self.abort().await;
+        }
+        // Tools that ignored SIGTERM outlive the CLI otherwise.
+        self.signal_group(libc::SIGKILL);
+        self.session.set_inactive().await;
         Ok(())
     }
+
+    fn exited(&self) -> bool {
+        self.exit.borrow().is_some()
+    }
+
+    fn signal_group(&self, signal: libc::c_int) {
+        if let Some(pgid) = self.pgid {
+            // SAFETY: plain signal delivery to the group our child leads.
+            unsafe {
+                libc::killpg(pgid, signal);
+            }
+        }
+    }
+
+    pub async fn wait(&self) -> Result<ExitStatus, String> {
+        let mut exit = self.exit.clone();
+        let status = exit
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| "Claude process was lost".to_string())?;
+        status
+            .clone()
+            .unwrap_or_else(|| Err("Claude process was lost".into()))
+    }
+}
+
+/// Lines of a multi-line trace kept in a single collapsed event.
+const MAX_TRACE_LINES: usize = 40;
+
+/// How long a held error waits for more trace lines before it is sent.
+const TRACE_FLUSH: Duration = Duration::from_millis(250);
+
+/// One classified chunk of claude's stderr.
+#[derive(Debug, Clone)]
+pub struct StderrEntry {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+    pub reset_at: Option<u64>,
+}
+
+impl StderrEntry {
+    /// Sends the entry, preceded by a `UsageLimit` event when it reports a
+    /// plan limit being hit or approached.
+    pub fn send(self, tx: &mpsc::UnboundedSender<Event>, request_id: Option<String>) {
+        if self.code == ErrorCode::RateLimited {
+            let _ = tx.send(Event::UsageLimit {
+                reset_at: self.reset_at,
+                limited: self.severity == Severity::Error,
+            });
+        }
+        let _ = tx.send(self.into_event(request_id));
+    }
+
+    pub fn into_event(self, request_id: Option<String>) -> Event {
+        match self.severity {
+            Severity::Error => {
+                let retry_after = self.reset_at.map(|at| at.saturating_sub(unix_now()));
+                BridgeError {
+                    retry_after,
+                    ..BridgeError::new(self.code, self.message)
+                }
+                .into_event(request_id)
+            }
+            severity => Event::Log {
+                severity,
+                message: self.message,
+            },
+        }
+    }
+}
+
+/// Folds stack frames that follow a panic or error header into that entry.
+#[derive(Default)]
+pub struct StderrClassifier {
+    trace: Option<StderrEntry>,
+    trace_lines: usize,
+}
+
+impl StderrClassifier {
+    pub fn push(&mut self, line: &str) -> Vec<StderrEntry> {
+        let mut out = Vec::new();
+        if line.trim().is_empty() {
+            return out;
+        }
+
+        if let Some(ref mut trace) = self.trace {
+            if is_trace_continuation(line) {
+                if self.trace_lines < MAX_TRACE_LINES {
+                    trace.message.push('\n');
+                    trace.message.push_str(line);
+                }
+                self.trace_lines += 1;
+                return out;
+            }
+        }
+        out.extend(self.finish());
+
+        let entry = classify_stderr_line(line);
+        if opens_trace(&entry) {
+            self.trace = Some(entry);
+            self.trace_lines = 1;
+        } else {
+            out.push(entry);
+        }
+        out
+    }
+
+    pub fn pending(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    pub fn finish(&mut self) -> Option<StderrEntry> {
+        let mut trace = self.trace.take()?;
+        if self.trace_lines > MAX_TRACE_LINES {
+            trace.message.push_str(&format!(
+                "\n... {} more lines",
+                self.trace_lines - MAX_TRACE_LINES
+            ));
+        }
+        self.trace_lines = 0;
+        Some(trace)
+    }
+}
+
+fn opens_trace(entry: &StderrEntry) -> bool {
+    matches!(entry.code, ErrorCode::CliPanic | ErrorCode::Stderr)
+        && entry.severity == Severity::Error
+}
+
+fn is_trace_continuation(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    line.starts_with(' ')
+        || line.starts_with('\t')
+        || trimmed.starts_with("at ")
+        || trimmed.starts_with("stack backtrace")
+        || trimmed.starts_with("note: run with")
+        || trimmed.starts_with("Caused by")
+}
+
+pub fn classify_stderr_line(line: &str) -> StderrEntry {
+    let lower = line.to_lowercase();
+    let entry = |severity, code| StderrEntry {
+        severity,
+        code,
+        message: line.to_string(),
+        reset_at: None,
+    };
+
+    if lower.contains("panicked at")
+        || lower.contains("uncaught exception")
+        || lower.contains("unhandled promise rejection")
+        || lower.starts_with("fatal error")
+    {
+        return entry(Severity::Error, ErrorCode::CliPanic);
+    }
+
+    match ErrorCode::from_cli_message(line) {
+        ErrorCode::RateLimited => {
+            let severity = if lower.contains("approaching") {
+                Severity::Warning
+            } else {
+                Severity::Error
+            };
+            return StderrEntry {
+                reset_at: parse_reset_at(line),
+                ..entry(severity, ErrorCode::RateLimited)
+            };
+        }
+        code @ (ErrorCode::Overloaded | ErrorCode::NotAuthenticated | ErrorCode::NetworkError) => {
+            return entry(Severity::Error, code);
+        }
+        _ => {}
+    }
+
+    if lower.contains("deprecat") || lower.starts_with("warning") || lower.starts_with("warn") {
+        entry(Severity::Warning, ErrorCode::Stderr)
+    } else if lower.contains("error:") || lower.starts_with("[error]") {
+        entry(Severity::Error, ErrorCode::Stderr)
+    } else {
+        entry(Severity::Log, ErrorCode::Stderr)
+    }
+}
+
+/// Extracts when a rate or usage limit lifts, as unix seconds. Understands
+/// the CLI's `usage limit reached|<epoch>` form and relative hints such as
+/// `retry after 30 seconds` or `try again in 5 minutes`.
+pub fn parse_reset_at(message: &str) -> Option<u64> {
+    if let Some((_, tail)) = message.rsplit_once('|') {
+        let digits: String = tail
+            .trim()
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        if let Ok(epoch) = digits.parse::<u64>() {
+            // Millisecond timestamps show up in some CLI versions.
+            return Some(if epoch > 100_000_000_000 {
+                epoch / 1000
+            } else {
+                epoch
+            });
+        }
+    }
+
+    let lower = message.to_lowercase();
+    for marker in [
+        "retry after",
+        "retry-after:",
+        "try again in",
+        "resets in",
+        "reset in",
+    ] {
+        if let Some(idx) = lower.find(marker) {
+            let rest = lower[idx + marker.len()..].trim_start();
+            let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+            let Ok(amount) = digits.parse::<u64>() else {
+                continue;
+            };
+            let unit = rest[digits.len()..].trim_start();
+            let secs = if unit.starts_with('h') {
+                amount * 3600
+            } else if unit.starts_with("ms") || unit.starts_with("milli") {
+                amount.div_ceil(1000)
+            } else if unit.starts_with('m') {
+                amount * 60
+            } else {
+                amount
+            };
+            return Some(unix_now() + secs);
+        }
+    }
+    None
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_debug_and_warning_lines() {
+        let entry = classify_stderr_line("[DEBUG] loading settings");
+        assert_eq!(entry.severity, Severity::Log);
+
+        let entry = classify_stderr_line("Warning: --foo is deprecated, use --bar");
+        assert_eq!(entry.severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_classify_error_lines() {
+        let entry = classify_stderr_line("Invalid API key · Please run /login");
+        assert_eq!(entry.code, ErrorCode::NotAuthenticated);
+
+        let entry = classify_stderr_line("Error: connect ECONNREFUSED 127.0.0.1:443");
+        assert_eq!(entry.code, ErrorCode::NetworkError);
+
+        let entry = classify_stderr_line("Claude AI usage limit reached|1760000000");
+        assert_eq!(entry.code, ErrorCode::RateLimited);
+        assert_eq!(entry.severity, Severity::Error);
+        assert_eq!(entry.reset_at, Some(1760000000));
+
+        let entry = classify_stderr_line("Approaching usage limit · resets in 2h");
+        assert_eq!(entry.code, ErrorCode::RateLimited);
+        assert_eq!(entry.severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_stderr_errors_are_fatal_by_code() {
+        let fatal = |line: &str| match classify_stderr_line(line).into_event(None) {
+            Event::Error { fatal, .. } => fatal,
+            other => panic!("expected an error, got {other:?}"),
+        };
+        assert!(fatal("Invalid API key · Please run /login"));
+        assert!(fatal("Error: connect ECONNREFUSED 127.0.0.1:443"));
+        assert!(!fatal("Error: something went sideways"));
+    }
+
+    #[test]
+    fn test_parse_relative_reset() {
+        let now = unix_now();
+        let at = parse_reset_at("Rate limited, try again in 5 minutes").unwrap();
+        assert!(at >= now + 300 && at <= now + 301);
+        let at = parse_reset_at("Overloaded, retry after 500ms").unwrap();
+        assert!(at > now && at <= now + 2);
+        let at = parse_reset_at("retry after 2500 milliseconds").unwrap();
+        assert!(at >= now + 3 && at <= now + 4);
+        assert!(parse_reset_at("nothing to see").is_none());
+    }
+
+    #[test]
+    fn test_collapse_stack_trace() {
+        let mut classifier = StderrClassifier::default();
+        assert!(classifier.push("TypeError: x is undefined").is_empty());
+        assert!(classifier.pending());
+        assert!(classifier.push("    at foo (cli.js:1:2)").is_empty());
+        assert!(classifier.push("    at bar (cli.js:3:4)").is_empty());
+
+        let out = classifier.push("[DEBUG] next");
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].severity, Severity::Error);
+        assert_eq!(out[0].message.lines().count(), 3);
+        assert_eq!(out[1].severity, Severity::Log);
+        assert!(!classifier.pending());
+        assert!(classifier.finish().is_none());
+    }
 }
@@ -0,0 +1,204 @@
+use crate::error::{BridgeError, ErrorCode};
+use crate::protocol::{ChatParams, ConfigureParams, LaunchOptions, Profile};
+use std::path::{Path, PathBuf};
+
+/// Defaults set with `configure`, applied to each turn as it starts.
+#[derive(Debug, Clone, Default)]
+pub struct BridgeConfig {
+    params: ConfigureParams,
+}
+
+impl BridgeConfig {
+    pub fn new(params: ConfigureParams) -> Self {
+        Self { params }
+    }
+
+    /// Fills in whatever `params` left to the bridge-wide defaults and its
+    /// profile, recording the profile's name in `params.profile`.
+    pub fn resolve(&self, params: &mut ChatParams) -> Result<(), BridgeError> {
+        let profile = match params.profile {
+            Some(ref name) => match self.params.profiles.get(name) {
+                Some(profile) => Some(profile),
+                None => {
+                    return Err(BridgeError::new(
+                        ErrorCode::InvalidRequest,
+                        format!("Unknown profile '{}'", name),
+                    ))
+                }
+            },
+            None => {
+                let cwd = params
+                    .cwd
+                    .as_ref()
+                    .map(PathBuf::from)
+                    .or_else(|| std::env::current_dir().ok());
+                match cwd.and_then(|cwd| self.profile_for(&cwd)) {
+                    Some((name, profile)) => {
+                        params.profile = Some(name.clone());
+                        Some(profile)
+                    }
+                    None => None,
+                }
+            }
+        };
+
+        if let Some(profile) = profile {
+            apply_profile(params, profile);
+        }
+        merge_launch(&mut params.launch, &self.params.launch);
+        Ok(())
+    }
+
+    pub fn shadow_commits(&self) -> bool {
+        self.params.shadow_commits
+    }
+
+    pub fn track_changes(&self) -> bool {
+        self.params.track_changes
+    }
+
+    /// The profile whose deepest path contains `cwd`.
+    fn profile_for(&self, cwd: &Path) -> Option<(&String, &Profile)> {
+        self.params
+            .profiles
+            .iter()
+            .flat_map(|(name, profile)| {
+                profile
+                    .paths
+                    .iter()
+                    .map(move |p| (expand_home(p), name, profile))
+            })
+            .filter(|(path, _, _)| cwd.starts_with(path))
+            .max_by_key(|(path, _, _)| path.components().count())
+            .map(|(_, name, profile)| (name, profile))
+    }
+}
+
+fn apply_profile(params: &mut ChatParams, profile: &Profile) {
+    if params.model.is_none() {
+        params.model = profile.model.clone();
+    }
+    if params.allowed_tools.is_none() {
+        params.allowed_tools = profile.allowed_tools.clone();
+    }
+    if params.mcp_config.is_none() {
+        params.mcp_config = profile
+            .mcp_config
+            .as_deref()
+            .map(|p| expand_home(p).display().to_string());
+    }
+    if let Some(ref dir) = profile.config_dir {
+        params
+            .launch
+            .env
+            .entry("CLAUDE_CONFIG_DIR".to_string())
+            .or_insert_with(|| Some(expand_home(dir).display().to_string()));
+    }
+}
+
+/// Expands a leading `~/` the way a shell would.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Request fields win; `env` is merged key by key.
+fn merge_launch(launch: &mut LaunchOptions, defaults: &LaunchOptions) {
+    if launch.claude_path.is_none() {
+        launch.claude_path = defaults.claude_path.clone();
+    }
+    if launch.env_passthrough.is_none() {
+        launch.env_passthrough = defaults.env_passthrough.clone();
+    }
+    for (name, value) in &defaults.env {
+        launch
+            .env
+            .entry(name.clone())
+            .or_insert_with(|| value.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_launch_options_override_defaults() {
+        let config = BridgeConfig::new(
+            serde_json::from_str(
+                r#"{"claude_path":"/opt/claude","env":{"A":"1","B":"2"},"env_passthrough":["PATH"]}"#,
+            )
+            .unwrap(),
+        );
+        let mut params: ChatParams =
+            serde_json::from_str(r#"{"prompt":"hi","env":{"B":null,"C":"3"}}"#).unwrap();
+        config.resolve(&mut params).unwrap();
+
+        assert_eq!(params.launch.claude_path.as_deref(), Some("/opt/claude"));
+        assert_eq!(params.launch.env["A"].as_deref(), Some("1"));
+        assert_eq!(params.launch.env["B"], None);
+        assert_eq!(params.launch.env["C"].as_deref(), Some("3"));
+        assert_eq!(
+            params.launch.env_passthrough,
+            Some(vec!["PATH".to_string()])
+        );
+    }
+
+    fn profiles_config() -> BridgeConfig {
+        BridgeConfig::new(
+            serde_json::from_str(
+                r#"{"profiles":{
+                    "work":{"paths":["/src/work"],"config_dir":"/cfg/work","model":"opus"},
+                    "client":{"paths":["/src/work/client"],"config_dir":"/cfg/client","mcp_config":"/cfg/mcp.json"}
+                }}"#,
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_profile_picked_by_deepest_path() {
+        let config = profiles_config();
+        let mut params: ChatParams =
+            serde_json::from_str(r#"{"prompt":"hi","cwd":"/src/work/client/app"}"#).unwrap();
+        config.resolve(&mut params).unwrap();
+
+        assert_eq!(params.profile.as_deref(), Some("client"));
+        assert_eq!(params.mcp_config.as_deref(), Some("/cfg/mcp.json"));
+        assert_eq!(
+            params.launch.env["CLAUDE_CONFIG_DIR"].as_deref(),
+            Some("/cfg/client")
+        );
+        assert!(params.model.is_none());
+    }
+
+    #[test]
+    fn test_path_match_respects_components() {
+        let config = profiles_config();
+        let mut params: ChatParams =
+            serde_json::from_str(r#"{"prompt":"hi","cwd":"/src/workshop"}"#).unwrap();
+        config.resolve(&mut params).unwrap();
+        assert!(params.profile.is_none());
+    }
+
+    #[test]
+    fn test_explicit_profile_keeps_request_settings() {
+        let config = profiles_config();
+        let mut params: ChatParams = serde_json::from_str(
+            r#"{"prompt":"hi","cwd":"/elsewhere","profile":"work","model":"sonnet"}"#,
+        )
+        .unwrap();
+        config.resolve(&mut params).unwrap();
+        assert_eq!(params.model.as_deref(), Some("sonnet"));
+        assert_eq!(
+            params.launch.env["CLAUDE_CONFIG_DIR"].as_deref(),
+            Some("/cfg/work")
+        );
+
+        params.profile = Some("missing".to_string());
+        let err = config.resolve(&mut params).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidRequest);
+    }
+}
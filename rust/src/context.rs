@@ -0,0 +1,178 @@
+use crate::error::{BridgeError, ErrorCode};
+use crate::git::git;
+use crate::protocol::{ContextProvider, ContextRequest};
+use std::path::Path;
+
+/// Budget for one provider's output when the request sets none.
+pub const DEFAULT_MAX_BYTES: usize = 32 * 1024;
+
+/// Commits `git_log` shows when the request sets no count.
+const DEFAULT_LOG_COUNT: u32 = 10;
+
+/// One provider's output, ready to join into a turn's context block.
+pub async fn render(cwd: &Path, request: &ContextRequest) -> Result<String, BridgeError> {
+    let (title, lang, out) = match request.provider {
+        ContextProvider::Diff { staged } => {
+            let args: &[&str] = if staged {
+                &["diff", "--cached"]
+            } else {
+                &["diff"]
+            };
+            let title = if staged {
+                "Git diff (staged)".to_string()
+            } else {
+                "Git diff (unstaged)".to_string()
+            };
+            (title, "diff", git(cwd, args).await?)
+        }
+        ContextProvider::DiffBase { ref base } => {
+            let base = revision(base)?;
+            let out = git(cwd, &["diff", base, "--"]).await?;
+            (format!("Git diff against {}", base), "diff", out)
+        }
+        ContextProvider::Log { count } => {
+            let count = format!("-n{}", count.unwrap_or(DEFAULT_LOG_COUNT));
+            let out = git(
+                cwd,
+                &["log", &count, "--date=short", "--format=%h %ad %an%n    %s"],
+            )
+            .await?;
+            ("Git log".to_string(), "", out)
+        }
+        ContextProvider::Blame {
+            ref path,
+            start_line,
+            end_line,
+        } => {
+            if start_line == 0 || end_line < start_line {
+                return Err(BridgeError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("Invalid blame range {}-{}", start_line, end_line),
+                ));
+            }
+            let range = format!("{},{}", start_line, end_line);
+            let out = git(cwd, &["blame", "--date=short", "-L", &range, "--", path]).await?;
+            (
+                format!("Git blame: {} (lines {}-{})", path, start_line, end_line),
+                "",
+                out,
+            )
+        }
+        ContextProvider::Status => {
+            let out = git(cwd, &["status", "--short", "--branch"]).await?;
+            ("Git status".to_string(), "", out)
+        }
+    };
+    let body = if out.is_empty() {
+        "(empty)".to_string()
+    } else {
+        truncate(&out, request.max_bytes.unwrap_or(DEFAULT_MAX_BYTES))
+    };
+    Ok(format!("{}\n```{}\n{}\n```", title, lang, body))
+}
+
+/// Refuses revisions git would read as options.
+fn revision(base: &str) -> Result<&str, BridgeError> {
+    if base.is_empty() || base.starts_with('-') {
+        return Err(BridgeError::new(
+            ErrorCode::InvalidRequest,
+            format!("Invalid revision '{}'", base),
+        ));
+    }
+    Ok(base)
+}
+
+/// Cuts `text` to at most `max_bytes`, at a line break when there is one,
+/// and notes how much was left out.
+pub fn truncate(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    if let Some(nl) = text[..end].rfind('\n') {
+        end = nl;
+    }
+    format!("{}\n[... {} more bytes]", &text[..end], text.len() - end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_respects_budget_at_line_break() {
+        assert_eq!(truncate("short", 10), "short");
+        let text = "line one\nline two\nline three\n";
+        let cut = truncate(text, 15);
+        assert_eq!(cut, "line one\n[... 21 more bytes]");
+        // Never splits a character.
+        assert_eq!(truncate("ééé", 3), "é\n[... 4 more bytes]");
+    }
+
+    #[tokio::test]
+    async fn test_render_git_providers() {
+        let dir = std::env::temp_dir().join(format!("claudecode-context-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "-q"]).await.unwrap();
+        std::fs::write(dir.join("a.txt"), "one\n").unwrap();
+        git(&dir, &["add", "a.txt"]).await.unwrap();
+        git(
+            &dir,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "-m",
+                "add a",
+            ],
+        )
+        .await
+        .unwrap();
+        std::fs::write(dir.join("a.txt"), "two\n").unwrap();
+
+        let request = |provider| ContextRequest {
+            provider,
+            max_bytes: None,
+        };
+        let diff = render(&dir, &request(ContextProvider::Diff { staged: false }))
+            .await
+            .unwrap();
+        assert!(diff.starts_with("Git diff (unstaged)\n```diff\n"));
+        assert!(diff.contains("+two"));
+        let staged = render(&dir, &request(ContextProvider::Diff { staged: true }))
+            .await
+            .unwrap();
+        assert!(staged.contains("(empty)"));
+        let log = render(&dir, &request(ContextProvider::Log { count: Some(1) }))
+            .await
+            .unwrap();
+        assert!(log.contains("add a"));
+        let blame = render(
+            &dir,
+            &request(ContextProvider::Blame {
+                path: "a.txt".to_string(),
+                start_line: 1,
+                end_line: 1,
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(blame.contains("Not Committed Yet"));
+        assert!(render(
+            &dir,
+            &request(ContextProvider::DiffBase {
+                base: "--output=x".to_string()
+            })
+        )
+        .await
+        .is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,281 @@
+use crate::claude;
+use crate::error::ErrorCode;
+use crate::protocol::{CheckStatus, DoctorCheck, LaunchOptions};
+use std::io::ErrorKind;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+
+/// How long any one CLI invocation may take.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Oldest CLI release the bridge is known to work with.
+const MIN_VERSION: Version = (1, 0, 0);
+/// First release the bridge has not been tried against.
+const UNTESTED_VERSION: Version = (3, 0, 0);
+
+type Version = (u64, u64, u64);
+
+/// Checks that the CLI `launch` points at can run turns for the bridge.
+pub async fn run(launch: &LaunchOptions) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+
+    let output = match run_cli(launch, &["--version"]).await {
+        Ok(output) => output,
+        Err(message) => {
+            checks.push(check("cli", CheckStatus::Fail, message));
+            return checks;
+        }
+    };
+    let version_text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    checks.push(check(
+        "cli",
+        CheckStatus::Pass,
+        format!("{} runs", launch.claude_path.as_deref().unwrap_or("claude")),
+    ));
+    checks.push(version_check(&version_text));
+
+    let (help, auth) = tokio::join!(run_cli(launch, &["--help"]), auth_check(launch));
+    checks.push(match help {
+        Ok(output) => stream_json_check(&String::from_utf8_lossy(&output.stdout)),
+        Err(message) => check("stream_json", CheckStatus::Warn, message),
+    });
+    checks.push(auth);
+    checks
+}
+
+fn check(name: &str, status: CheckStatus, message: impl Into<String>) -> DoctorCheck {
+    DoctorCheck {
+        name: name.to_string(),
+        status,
+        message: message.into(),
+    }
+}
+
+async fn run_cli(launch: &LaunchOptions, args: &[&str]) -> Result<Output, String> {
+    let mut cmd = claude::command(launch);
+    cmd.args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let invocation = format!("claude {}", args.join(" "));
+    match tokio::time::timeout(CHECK_TIMEOUT, cmd.output()).await {
+        Err(_) => Err(format!(
+            "`{}` did not finish within {}s",
+            invocation,
+            CHECK_TIMEOUT.as_secs()
+        )),
+        Ok(Err(e)) if e.kind() == ErrorKind::NotFound => Err(format!(
+            "claude CLI not found ({}); install it or set claude_path",
+            e
+        )),
+        Ok(Err(e)) => Err(format!("Failed to run `{}`: {}", invocation, e)),
+        Ok(Ok(output)) => Ok(output),
+    }
+}
+
+/// Finds the first `x.y.z` in output such as `1.0.93 (Claude Code)`.
+fn parse_version(text: &str) -> Option<Version> {
+    text.split_whitespace().find_map(|word| {
+        let mut parts = word.trim_start_matches('v').split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts
+            .next()?
+            .split(|c: char| !c.is_ascii_digit())
+            .next()?
+            .parse()
+            .ok()?;
+        Some((major, minor, patch))
+    })
+}
+
+fn version_check(text: &str) -> DoctorCheck {
+    let Some(version) = parse_version(text) else {
+        return check(
+            "version",
+            CheckStatus::Warn,
+            format!("Could not parse the CLI version from {:?}", text),
+        );
+    };
+    let shown = format!("{}.{}.{}", version.0, version.1, version.2);
+    if version < MIN_VERSION {
+        check(
+            "version",
+            CheckStatus::Fail,
+            format!(
+                "claude {} is older than the supported {}.{}.{}; please upgrade",
+                shown, MIN_VERSION.0, MIN_VERSION.1, MIN_VERSION.2
+            ),
+        )
+    } else if version >= UNTESTED_VERSION {
+        check(
+            "version",
+            CheckStatus::Warn,
+            format!(
+                "claude {} is newer than the bridge has been tested with",
+                shown
+            ),
+        )
+    } else {
+        check("version", CheckStatus::Pass, format!("claude {}", shown))
+    }
+}
+
+fn stream_json_check(help: &str) -> DoctorCheck {
+    if !help.contains("stream-json") {
+        check(
+            "stream_json",
+            CheckStatus::Fail,
+            "The CLI does not offer stream-json input/output",
+        )
+    } else if !help.contains("--include-partial-messages") {
+        check(
+            "stream_json",
+            CheckStatus::Fail,
+            "The CLI does not support --include-partial-messages; please upgrade",
+        )
+    } else if !help.contains("--input-format") {
+        check(
+            "stream_json",
+            CheckStatus::Fail,
+            "The CLI does not support --input-format stream-json, so running turns cannot be steered; please upgrade",
+        )
+    } else {
+        check(
+            "stream_json",
+            CheckStatus::Pass,
+            "stream-json input and output with partial messages is supported",
+        )
+    }
+}
+
+async fn auth_check(launch: &LaunchOptions) -> DoctorCheck {
+    let api_key = match launch.env.get("ANTHROPIC_API_KEY") {
+        Some(value) => value.clone(),
+        None if launch
+            .env_passthrough
+            .as_ref()
+            .is_some_and(|names| !names.iter().any(|n| n == "ANTHROPIC_API_KEY")) =>
+        {
+            None
+        }
+        None => std::env::var("ANTHROPIC_API_KEY").ok(),
+    };
+    if api_key.is_some_and(|key| !key.is_empty()) {
+        return check("auth", CheckStatus::Pass, "Using ANTHROPIC_API_KEY");
+    }
+
+    match run_cli(launch, &["auth", "status"]).await {
+        Ok(output) => auth_status_check(
+            output.status.success(),
+            &String::from_utf8_lossy(&output.stdout),
+            &String::from_utf8_lossy(&output.stderr),
+        ),
+        Err(message) => check("auth", CheckStatus::Warn, message),
+    }
+}
+
+fn auth_status_check(success: bool, stdout: &str, stderr: &str) -> DoctorCheck {
+    let first_line = stdout
+        .lines()
+        .chain(stderr.lines())
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    let all = format!("{}\n{}", stdout, stderr);
+    if success {
+        check("auth", CheckStatus::Pass, first_line)
+    } else if is_unknown_command(&all) {
+        // Releases without `auth status` can still be logged in.
+        check(
+            "auth",
+            CheckStatus::Warn,
+            "This CLI has no `auth status`; login state not checked",
+        )
+    } else if ErrorCode::from_cli_message(&all) == ErrorCode::NotAuthenticated {
+        check(
+            "auth",
+            CheckStatus::Fail,
+            format!("{}; run `claude` and /login", first_line),
+        )
+    } else {
+        check(
+            "auth",
+            CheckStatus::Warn,
+            format!("Could not determine login state: {}", first_line),
+        )
+    }
+}
+
+fn is_unknown_command(output: &str) -> bool {
+    let lower = output.to_lowercase();
+    [
+        "unknown command",
+        "unknown subcommand",
+        "unrecognized",
+        "too many arguments",
+    ]
+    .iter()
+    .any(|p| lower.contains(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("1.0.93 (Claude Code)"), Some((1, 0, 93)));
+        assert_eq!(parse_version("claude v2.1.4-beta"), Some((2, 1, 4)));
+        assert_eq!(parse_version("unknown"), None);
+    }
+
+    #[test]
+    fn test_version_check_range() {
+        assert_eq!(version_check("0.2.9").status, CheckStatus::Fail);
+        assert_eq!(
+            version_check("1.0.93 (Claude Code)").status,
+            CheckStatus::Pass
+        );
+        assert_eq!(version_check("3.0.0").status, CheckStatus::Warn);
+        assert_eq!(version_check("garbled").status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn test_auth_status_check() {
+        assert_eq!(
+            auth_status_check(true, "Logged in as a@b.c\n", "").status,
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            auth_status_check(false, "", "Not logged in. Please run /login").status,
+            CheckStatus::Fail
+        );
+        // An older CLI that lacks the subcommand, complaining about auth
+        // on the way.
+        let unknown = auth_status_check(
+            false,
+            "",
+            "error: unknown command 'auth'\nauthentication options: --api-key",
+        );
+        assert_eq!(unknown.status, CheckStatus::Warn);
+        assert!(unknown.message.contains("no `auth status`"));
+    }
+
+    #[test]
+    fn test_stream_json_check() {
+        let output = "--output-format <format> (choices: \"text\", \"json\", \"stream-json\")\n--include-partial-messages";
+        let help = format!(
+            "--input-format <format> (choices: \"text\", \"stream-json\")\n{}",
+            output
+        );
+        assert_eq!(stream_json_check(&help).status, CheckStatus::Pass);
+        assert_eq!(stream_json_check(output).status, CheckStatus::Fail);
+        assert_eq!(
+            stream_json_check("--output-format stream-json").status,
+            CheckStatus::Fail
+        );
+    }
+}
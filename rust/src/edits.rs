@@ -0,0 +1,371 @@
+use crate::protocol::{DiffHunk, Event};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use similar::{ChangeTag, TextDiff};
+use std::path::{Path, PathBuf};
+
+/// Lines of unchanged context around each hunk.
+const CONTEXT_LINES: usize = 3;
+
+/// Hex SHA-256 of file contents, matching `vim.fn.sha256` in the editor.
+pub fn content_hash(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The file a file-modifying tool call writes, resolved against `cwd`.
+pub fn target_path(tool: &str, input: &Value, cwd: Option<&str>) -> Option<PathBuf> {
+    let key = match tool {
+        "Edit" | "MultiEdit" | "Write" => "file_path",
+        "NotebookEdit" => "notebook_path",
+        _ => return None,
+    };
+    resolve(input.get(key)?.as_str()?, cwd)
+}
+
+/// The file a Read tool call reads, resolved against `cwd`.
+pub fn read_path(tool: &str, input: &Value, cwd: Option<&str>) -> Option<PathBuf> {
+    if tool != "Read" {
+        return None;
+    }
+    resolve(input.get("file_path")?.as_str()?, cwd)
+}
+
+fn resolve(path: &str, cwd: Option<&str>) -> Option<PathBuf> {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return Some(path.to_path_buf());
+    }
+    let base = match cwd {
+        Some(cwd) => PathBuf::from(cwd),
+        None => std::env::current_dir().ok()?,
+    };
+    Some(base.join(path))
+}
+
+/// Contents of a file after an edit, plus how well the edit matched.
+struct Applied {
+    /// What to diff against when not the file as read.
+    base: Option<String>,
+    content: String,
+    ambiguous: bool,
+    unmatched: bool,
+}
+
+/// A `proposed_change` event with the two versions it was computed from.
+pub struct Proposal {
+    pub event: Event,
+    pub base: String,
+    pub content: String,
+}
+
+/// Works out the change a file-modifying tool call proposes to its target,
+/// which held `before` (`None` if it did not exist). `None` for other tools
+/// or binary files.
+pub fn propose(
+    tool: &str,
+    id: &str,
+    input: &Value,
+    cwd: Option<&str>,
+    before: Option<&[u8]>,
+) -> Option<Proposal> {
+    let path = target_path(tool, input, cwd)?;
+    let old = match before {
+        Some(bytes) => Some(String::from_utf8(bytes.to_vec()).ok()?),
+        None => None,
+    };
+    let original = old.as_deref().unwrap_or("");
+
+    let applied = match tool {
+        "Edit" => apply_edits(original, std::slice::from_ref(input)),
+        "MultiEdit" => apply_edits(original, input.get("edits")?.as_array()?),
+        "Write" => Applied {
+            base: None,
+            content: input.get("content")?.as_str()?.to_string(),
+            ambiguous: false,
+            unmatched: false,
+        },
+        "NotebookEdit" => apply_notebook_edit(original, input)?,
+        _ => return None,
+    };
+
+    // Headers name the file relative to the project when it is inside it.
+    let label = cwd
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .unwrap_or(&path)
+        .display()
+        .to_string();
+    let base = applied.base.unwrap_or_else(|| original.to_string());
+    let (diff, hunks) = unified_diff(&base, &applied.content, &label);
+    let event = Event::ProposedChange {
+        tool_id: id.to_string(),
+        path: path.display().to_string(),
+        old_hash: old.as_ref().map(|o| content_hash(o.as_bytes())),
+        new_hash: content_hash(applied.content.as_bytes()),
+        diff,
+        hunks,
+        ambiguous: applied.ambiguous,
+        unmatched: applied.unmatched,
+    };
+    Some(Proposal {
+        event,
+        base,
+        content: applied.content,
+    })
+}
+
+/// Applies `old_string`/`new_string` edits in order, the way the CLI does:
+/// the first match only unless `replace_all` is set. Edits that match
+/// nowhere are skipped.
+fn apply_edits(original: &str, edits: &[Value]) -> Applied {
+    let mut content = original.to_string();
+    let mut ambiguous = false;
+    let mut unmatched = false;
+    for edit in edits {
+        let old = edit
+            .get("old_string")
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+        let new = edit
+            .get("new_string")
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+        let replace_all = edit
+            .get("replace_all")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        if old.is_empty() {
+            // Creating a file from nothing.
+            if content.is_empty() {
+                content = new.to_string();
+            } else {
+                unmatched = true;
+            }
+            continue;
+        }
+        match content.matches(old).count() {
+            0 => unmatched = true,
+            n => {
+                ambiguous |= n > 1 && !replace_all;
+                content = if replace_all {
+                    content.replace(old, new)
+                } else {
+                    content.replacen(old, new, 1)
+                };
+            }
+        }
+    }
+    Applied {
+        base: None,
+        content,
+        ambiguous,
+        unmatched,
+    }
+}
+
+/// Applies a NotebookEdit to the notebook JSON. Both versions are
+/// re-serialized so the diff only shows the cells that changed.
+fn apply_notebook_edit(original: &str, input: &Value) -> Option<Applied> {
+    let mut notebook: Value = serde_json::from_str(original).ok()?;
+    let base = to_notebook_json(&notebook)?;
+    let new_source = input.get("new_source")?.as_str()?;
+    let mode = input
+        .get("edit_mode")
+        .and_then(|v| v.as_str())
+        .unwrap_or("replace");
+    let cell_id = input.get("cell_id").and_then(|v| v.as_str());
+
+    let cells = notebook.get_mut("cells")?.as_array_mut()?;
+    let index = cell_id.and_then(|id| {
+        cells
+            .iter()
+            .position(|c| c.get("id").and_then(|v| v.as_str()) == Some(id))
+    });
+    let mut unmatched = cell_id.is_some() && index.is_none();
+
+    match mode {
+        "insert" => {
+            let cell_type = input
+                .get("cell_type")
+                .and_then(|v| v.as_str())
+                .unwrap_or("code");
+            let mut cell = serde_json::json!({
+                "cell_type": cell_type,
+                "metadata": {},
+                "source": new_source,
+            });
+            if cell_type == "code" {
+                cell["outputs"] = serde_json::json!([]);
+                cell["execution_count"] = Value::Null;
+            }
+            let at = index.map_or(0, |i| i + 1);
+            cells.insert(at.min(cells.len()), cell);
+        }
+        "delete" => match index {
+            Some(i) => {
+                cells.remove(i);
+            }
+            None => unmatched = true,
+        },
+        _ => match index {
+            Some(i) => cells[i]["source"] = Value::String(new_source.to_string()),
+            None => unmatched = true,
+        },
+    }
+
+    Some(Applied {
+        base: Some(base),
+        content: to_notebook_json(&notebook)?,
+        ambiguous: false,
+        unmatched,
+    })
+}
+
+fn to_notebook_json(value: &Value) -> Option<String> {
+    let mut out = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b" ");
+    let mut ser = serde_json::Serializer::with_formatter(&mut out, formatter);
+    serde::Serialize::serialize(value, &mut ser).ok()?;
+    let mut text = String::from_utf8(out).ok()?;
+    text.push('\n');
+    Some(text)
+}
+
+/// A unified diff of `old` against `new`, and the same hunks in structured
+/// form with 1-based line numbers.
+pub fn unified_diff(old: &str, new: &str, path: &str) -> (String, Vec<DiffHunk>) {
+    let diff = TextDiff::from_lines(old, new);
+    let text = diff
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&header_path("a", path), &header_path("b", path))
+        .to_string();
+
+    let hunks = diff
+        .grouped_ops(CONTEXT_LINES)
+        .iter()
+        .filter_map(|group| {
+            let first = group.first()?;
+            let last = group.last()?;
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+            let lines = group
+                .iter()
+                .flat_map(|op| diff.iter_changes(op))
+                .map(|change| {
+                    let sign = match change.tag() {
+                        ChangeTag::Equal => ' ',
+                        ChangeTag::Delete => '-',
+                        ChangeTag::Insert => '+',
+                    };
+                    let value = change.value();
+                    format!("{}{}", sign, value.strip_suffix('\n').unwrap_or(value))
+                })
+                .collect();
+            Some(DiffHunk {
+                old_start: hunk_start(&old_range),
+                old_lines: old_range.len(),
+                new_start: hunk_start(&new_range),
+                new_lines: new_range.len(),
+                lines,
+            })
+        })
+        .collect();
+    (text, hunks)
+}
+
+/// 1-based first line of a hunk side, or for an empty side the line it
+/// follows (0 at the top of the file), as in unified diff headers.
+fn hunk_start(range: &std::ops::Range<usize>) -> usize {
+    if range.is_empty() {
+        range.start
+    } else {
+        range.start + 1
+    }
+}
+
+fn header_path(side: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/{}", side, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_content_hash_is_sha256() {
+        assert_eq!(
+            content_hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_apply_edit_flags_ambiguous_and_unmatched() {
+        let applied = apply_edits("a b a", &[json!({"old_string": "a", "new_string": "x"})]);
+        assert_eq!(applied.content, "x b a");
+        assert!(applied.ambiguous);
+
+        let applied = apply_edits(
+            "a b a",
+            &[json!({"old_string": "a", "new_string": "x", "replace_all": true})],
+        );
+        assert_eq!(applied.content, "x b x");
+        assert!(!applied.ambiguous);
+
+        let applied = apply_edits("a b", &[json!({"old_string": "z", "new_string": "x"})]);
+        assert_eq!(applied.content, "a b");
+        assert!(applied.unmatched);
+    }
+
+    #[test]
+    fn test_unified_diff_hunk_line_numbers() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\n3\n4\n5\nsix\n7\n8\n9\n10\n";
+        let (text, hunks) = unified_diff(old, new, "f.txt");
+        assert!(text.starts_with("--- a/f.txt\n+++ b/f.txt\n@@ -3,7 +3,7 @@"));
+        assert_eq!(hunks.len(), 1);
+        let h = &hunks[0];
+        assert_eq!(
+            (h.old_start, h.old_lines, h.new_start, h.new_lines),
+            (3, 7, 3, 7)
+        );
+        assert!(h.lines.contains(&"-6".to_string()));
+        assert!(h.lines.contains(&"+six".to_string()));
+    }
+
+    #[test]
+    fn test_unified_diff_empty_side_starts_at_preceding_line() {
+        let (text, hunks) = unified_diff("", "a\nb\n", "f.txt");
+        assert!(text.contains("@@ -0,0 +1,2 @@"));
+        let h = &hunks[0];
+        assert_eq!(
+            (h.old_start, h.old_lines, h.new_start, h.new_lines),
+            (0, 0, 1, 2)
+        );
+
+        let (text, hunks) = unified_diff("a\nb\n", "", "f.txt");
+        assert!(text.contains("@@ -1,2 +0,0 @@"));
+        assert_eq!((hunks[0].new_start, hunks[0].new_lines), (0, 0));
+    }
+
+    #[test]
+    fn test_notebook_replace_cell() {
+        let nb = r#"{"cells":[{"id":"c1","cell_type":"code","source":"x = 1","metadata":{},"outputs":[],"execution_count":null}],"metadata":{},"nbformat":4,"nbformat_minor":5}"#;
+        let applied = apply_notebook_edit(
+            nb,
+            &json!({"notebook_path": "n.ipynb", "cell_id": "c1", "new_source": "x = 2"}),
+        )
+        .unwrap();
+        assert!(applied.content.contains("x = 2"));
+        assert!(!applied.unmatched);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::protocol::Event;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    SpawnFailed,
+    CliNotFound,
+    NotAuthenticated,
+    RateLimited,
+    Overloaded,
+    ModelUnavailable,
+    InvalidRequest,
+    NoSession,
+    Busy,
+    CliExitNonzero,
+    CliPanic,
+    Timeout,
+    NetworkError,
+    CliError,
+    Stderr,
+    GitFailed,
+}
+
+impl ErrorCode {
+    /// Failures that another model may not share.
+    pub fn is_model_failure(self) -> bool {
+        matches!(self, ErrorCode::Overloaded | ErrorCode::ModelUnavailable)
+    }
+
+    /// The code for an HTTP status the API answered with, if it has one.
+    pub fn from_status(status: u64) -> Option<Self> {
+        match status {
+            529 => Some(ErrorCode::Overloaded),
+            503 => Some(ErrorCode::ModelUnavailable),
+            429 => Some(ErrorCode::RateLimited),
+            401 => Some(ErrorCode::NotAuthenticated),
+            _ => None,
+        }
+    }
+
+    /// Best-effort mapping of a CLI error message (an `is_error` result or
+    /// an API error string) onto a code the UI can act on.
+    pub fn from_cli_message(message: &str) -> Self {
+        let lower = message.to_lowercase();
+        // Status codes only count as whole words, not as part of a line
+        // number, port or byte count.
+        let status = |code: &str| {
+            lower
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|word| word == code)
+        };
+        if lower.contains("overloaded") || status("529") {
+            ErrorCode::Overloaded
+        } else if lower.contains("not_found_error")
+            || lower.contains("model not found")
+            || (lower.contains("model") && lower.contains("not available"))
+            || status("503")
+            || lower.contains("service unavailable")
+        {
+            ErrorCode::ModelUnavailable
+        } else if lower.contains("rate limit")
+            || lower.contains("rate_limit")
+            || lower.contains("usage limit")
+            || status("429")
+        {
+            ErrorCode::RateLimited
+        } else if lower.contains("invalid api key")
+            || lower.contains("not logged in")
+            || lower.contains("please run /login")
+            || lower.contains("authentication")
+            || status("401")
+        {
+            ErrorCode::NotAuthenticated
+        } else if is_network_error(&lower) {
+            ErrorCode::NetworkError
+        } else {
+            ErrorCode::CliError
+        }
+    }
+}
+
+fn is_network_error(lower: &str) -> bool {
+    [
+        "econnrefused",
+        "econnreset",
+        "etimedout",
+        "enotfound",
+        "eai_again",
+        "socket hang up",
+        "network error",
+        "connection error",
+        "fetch failed",
+    ]
+    .iter()
+    .any(|needle| lower.contains(needle))
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub fatal: bool,
+    pub retry_after: Option<u64>,
+}
+
+impl BridgeError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            // Codes that leave any running turn untouched.
+            fatal: !matches!(
+                code,
+                ErrorCode::Stderr | ErrorCode::Busy | ErrorCode::InvalidRequest
+            ),
+            retry_after: None,
+        }
+    }
+
+    pub fn into_event(self, request_id: Option<String>) -> Event {
+        Event::Error {
+            code: self.code,
+            message: self.message,
+            fatal: self.fatal,
+            retry_after: self.retry_after,
+            request_id,
+        }
+    }
+}
+
+impl std::fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
@@ -0,0 +1,34 @@
+use crate::error::{BridgeError, ErrorCode};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Runs git in `dir` and returns its trimmed stdout.
+pub async fn git(dir: &Path, args: &[&str]) -> Result<String, BridgeError> {
+    git_env(dir, args, &[]).await
+}
+
+/// Like `git`, with extra environment variables.
+pub async fn git_env(
+    dir: &Path,
+    args: &[&str],
+    env: &[(&str, &str)],
+) -> Result<String, BridgeError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| BridgeError::new(ErrorCode::GitFailed, format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(BridgeError::new(
+            ErrorCode::GitFailed,
+            format!("git {} failed: {}", args.join(" "), stderr.trim()),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,256 @@
+use crate::edits::target_path;
+use crate::usage::state_dir;
+use serde_json::Value;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Tools whose target the hook saves before they run.
+const WRITE_TOOLS: &str = "Edit|MultiEdit|Write|NotebookEdit";
+
+/// Contents of a file before a tool call ran; `None` if it did not exist.
+pub type PreImage = Option<Vec<u8>>;
+
+/// Files as they were just before the CLI's file-modifying tools ran,
+/// saved by a PreToolUse hook. The bridge only sees a tool call once the
+/// CLI streams it, by which time the tool may already have written.
+#[derive(Clone)]
+pub struct PreImages {
+    /// `None` when the spool directory could not be set up; pre-images are
+    /// then read when the tool call arrives.
+    dir: Option<PathBuf>,
+}
+
+impl PreImages {
+    /// A spool under the runtime directory, with the settings file that
+    /// installs the hook.
+    pub fn new() -> Self {
+        let base = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .or_else(state_dir)
+            .unwrap_or_else(std::env::temp_dir);
+        Self::under(Some(&base))
+    }
+
+    /// A spool in a new directory under `base`. The CLI runs whatever hook
+    /// the settings file names, so the directory must be one only we could
+    /// have made.
+    pub fn under(base: Option<&Path>) -> Self {
+        let dir = base.and_then(|base| {
+            let exe = std::env::current_exe().ok()?;
+            std::fs::create_dir_all(base).ok()?;
+            let dir = private_dir(base, "claudecode-pre-images").ok()?;
+            let written = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(dir.join("settings.json"))
+                .and_then(|mut f| f.write_all(settings(&exe, &dir).as_bytes()));
+            match written {
+                Ok(()) => Some(dir),
+                Err(_) => {
+                    let _ = std::fs::remove_dir_all(&dir);
+                    None
+                }
+            }
+        });
+        Self { dir }
+    }
+
+    /// Settings file for `--settings`, when the hook is available.
+    pub fn settings(&self) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join("settings.json"))
+    }
+
+    /// What `path` held before tool call `id` wrote it. The file is read
+    /// before the spool is checked: if the hook has not saved it yet, the
+    /// tool has not run yet either.
+    pub async fn take(&self, id: &str, path: &Path) -> std::io::Result<PreImage> {
+        let current = tokio::fs::read(path).await;
+        if let Some(ref dir) = self.dir {
+            let saved = dir.join(spool_name(id));
+            if let Ok(bytes) = tokio::fs::read(&saved).await {
+                let _ = tokio::fs::remove_file(&saved).await;
+                return Ok(Some(bytes));
+            }
+            let absent = saved.with_extension("absent");
+            if tokio::fs::remove_file(&absent).await.is_ok() {
+                return Ok(None);
+            }
+        }
+        match current {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Drops pre-images no tool call claimed, e.g. saved after the bridge
+    /// had already read the file.
+    pub async fn clear(&self) {
+        let Some(ref dir) = self.dir else {
+            return;
+        };
+        let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_name() != "settings.json" {
+                let _ = tokio::fs::remove_file(entry.path()).await;
+            }
+        }
+    }
+
+    pub fn remove(&self) {
+        if let Some(ref dir) = self.dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+fn settings(exe: &Path, dir: &Path) -> String {
+    let command = format!(
+        "{} pre-image {}",
+        shell_quote(&exe.display().to_string()),
+        shell_quote(&dir.display().to_string())
+    );
+    serde_json::json!({
+        "hooks": {
+            "PreToolUse": [{
+                "matcher": WRITE_TOOLS,
+                "hooks": [{"type": "command", "command": command}],
+            }],
+        },
+    })
+    .to_string()
+}
+
+/// Makes a directory under `base` that did not exist before, readable by
+/// us alone. A name already taken, by a directory or a symlink, is skipped.
+fn private_dir(base: &Path, prefix: &str) -> std::io::Result<PathBuf> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let mut last = ErrorKind::AlreadyExists.into();
+    for attempt in 0..16u32 {
+        let name = format!(
+            "{}-{}-{:08x}",
+            prefix,
+            std::process::id(),
+            nanos.wrapping_add(attempt.wrapping_mul(0x9e37_79b9))
+        );
+        let dir = base.join(name);
+        match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => last = e,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last)
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Tool ids as safe file names.
+fn spool_name(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// `claudecode-bridge pre-image DIR`: the hook itself. Reads the hook input
+/// from stdin and saves the target file to `DIR`. Never blocks the tool.
+pub fn command(args: &[String]) -> i32 {
+    let mut input = String::new();
+    if let (Some(dir), Ok(_)) = (args.first(), std::io::stdin().read_to_string(&mut input)) {
+        if let Ok(input) = serde_json::from_str::<Value>(&input) {
+            save(&input, Path::new(dir));
+        }
+    }
+    0
+}
+
+fn save(input: &Value, dir: &Path) {
+    let field = |name| input.get(name).and_then(|v| v.as_str());
+    let (Some(tool), Some(id), Some(tool_input)) = (
+        field("tool_name"),
+        field("tool_use_id"),
+        input.get("tool_input"),
+    ) else {
+        return;
+    };
+    let Some(path) = target_path(tool, tool_input, field("cwd")) else {
+        return;
+    };
+    let saved = dir.join(spool_name(id));
+    match std::fs::read(&path) {
+        Ok(bytes) => {
+            // Renamed into place so the bridge never reads half a file.
+            let partial = saved.with_extension("partial");
+            if std::fs::write(&partial, bytes).is_ok() {
+                let _ = std::fs::rename(&partial, &saved);
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let _ = std::fs::write(saved.with_extension("absent"), "");
+        }
+        Err(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_saved_pre_image_wins_over_written_file() {
+        let dir = private_dir(&std::env::temp_dir(), "claudecode-hook").unwrap();
+        let spool = PreImages::under(Some(&dir));
+        let spool_dir = spool.dir.clone().unwrap();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "before\n").unwrap();
+
+        // The hook ran and the tool wrote before the bridge saw the call.
+        let hook_input = |id: &str, path: &Path| {
+            json!({
+                "tool_name": "Edit",
+                "tool_use_id": id,
+                "tool_input": {"file_path": path},
+                "cwd": "/",
+            })
+        };
+        save(&hook_input("toolu_1", &file), &spool_dir);
+        std::fs::write(&file, "after\n").unwrap();
+        assert_eq!(
+            spool.take("toolu_1", &file).await.unwrap(),
+            Some(b"before\n".to_vec())
+        );
+        // Nothing saved: the file as it is now.
+        assert_eq!(
+            spool.take("toolu_2", &file).await.unwrap(),
+            Some(b"after\n".to_vec())
+        );
+
+        let new_file = dir.join("new.txt");
+        save(&hook_input("toolu_3", &new_file), &spool_dir);
+        std::fs::write(&new_file, "created\n").unwrap();
+        assert_eq!(spool.take("toolu_3", &new_file).await.unwrap(), None);
+
+        let settings = std::fs::read_to_string(spool.settings().unwrap()).unwrap();
+        assert!(settings.contains("pre-image"));
+        let mode = std::fs::metadata(&spool_dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        spool.remove();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
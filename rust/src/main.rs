@@ -1,205 +1,297 @@
+mod bridge;
+mod buffers;
+mod changeset;
+mod checkpoint;
 mod claude;
+mod config;
+mod context;
+mod doctor;
+mod edits;
+mod error;
+mod git;
+mod hook;
+mod mentions;
+mod merge;
 mod protocol;
 mod session;
+mod shadow;
+mod usage;
+mod watcher;
+mod worktree;
 
-use protocol::{Event, Request};
-use session::SessionManager;
+use bridge::{Bridge, SessionTarget};
+use config::BridgeConfig;
+use error::{BridgeError, ErrorCode};
+use protocol::{BusyBehavior, ChatParams, CheckStatus, ConfigureParams, Envelope, Event, Request};
+use std::time::Duration;
 use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 
-fn spawn_event_forwarder(
-    mut rx: mpsc::UnboundedReceiver<Event>,
-    active_process: Arc<Mutex<Option<claude::ClaudeProcess>>>,
-) -> JoinHandle<()> {
+/// How long pending output gets to drain before the bridge exits anyway.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn spawn_output_writer(mut rx: mpsc::UnboundedReceiver<Event>) -> JoinHandle<()> {
     tokio::spawn(async move {
+        let mut out = io::stdout();
         while let Some(event) = rx.recv().await {
             let line = event.to_json_line();
-            let mut out = io::stdout();
             let _ = out.write_all(line.as_bytes()).await;
             let _ = out.flush().await;
+        }
+    })
+}
 
-            if matches!(event, Event::Done) {
-                let mut proc = active_process.lock().await;
-                if let Some(ref mut p) = *proc {
-                    let _ = p.wait().await;
+/// Resolves with the exit code for the first SIGTERM, SIGINT or SIGHUP,
+/// following the shell's 128 + signal number convention.
+/// Never resolves if the handlers cannot be installed.
+async fn shutdown_signal() -> i32 {
+    let streams = (|| {
+        Ok::<_, io::Error>((
+            signal(SignalKind::terminate())?,
+            signal(SignalKind::interrupt())?,
+            signal(SignalKind::hangup())?,
+        ))
+    })();
+    let (mut term, mut int, mut hup) = match streams {
+        Ok(streams) => streams,
+        Err(e) => {
+            eprintln!("claudecode-bridge: cannot watch signals: {}", e);
+            return std::future::pending().await;
+        }
+    };
+    let signo = tokio::select! {
+        _ = term.recv() => libc::SIGTERM,
+        _ = int.recv() => libc::SIGINT,
+        _ = hup.recv() => libc::SIGHUP,
+    };
+    128 + signo
+}
+
+/// `claudecode-bridge doctor [--json] [--claude-path PATH] [--config JSON]`:
+/// checks the CLI and exits non-zero if anything failed. `--config` takes the
+/// params of a `configure` request, resolved for the current directory.
+async fn doctor_command(args: &[String]) -> i32 {
+    let mut params = ChatParams::default();
+    let mut config = ConfigureParams::default();
+    let mut json = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--claude-path" => params.launch.claude_path = args.next().cloned(),
+            "--config" => match args.next().map(|c| serde_json::from_str(c)) {
+                Some(Ok(parsed)) => config = parsed,
+                Some(Err(e)) => {
+                    eprintln!("claudecode-bridge doctor: invalid --config: {}", e);
+                    return 2;
+                }
+                None => {
+                    eprintln!("claudecode-bridge doctor: --config needs a value");
+                    return 2;
                 }
-                *proc = None;
-                break;
+            },
+            other => {
+                eprintln!("claudecode-bridge doctor: unknown argument {}", other);
+                return 2;
             }
         }
-    })
+    }
+    // An unmatched profile still leaves the plain defaults.
+    let _ = BridgeConfig::new(config).resolve(&mut params);
+
+    let checks = doctor::run(&params.launch).await;
+    let failed = checks.iter().any(|c| c.status == CheckStatus::Fail);
+    if json {
+        print!("{}", Event::Doctor { checks }.to_json_line());
+    } else {
+        for c in checks {
+            let label = match c.status {
+                CheckStatus::Pass => "ok",
+                CheckStatus::Warn => "warn",
+                CheckStatus::Fail => "FAIL",
+            };
+            println!("[{}] {}: {}", label, c.name, c.message);
+        }
+    }
+    i32::from(failed)
 }
 
 #[tokio::main]
 async fn main() {
-    let session = SessionManager::new();
-    let active_process: Arc<Mutex<Option<claude::ClaudeProcess>>> = Arc::new(Mutex::new(None));
-    let active_forwarder: Arc<Mutex<Option<JoinHandle<()>>>> = Arc::new(Mutex::new(None));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("doctor") {
+        std::process::exit(doctor_command(&args[1..]).await);
+    }
+    if args.first().map(String::as_str) == Some("pre-image") {
+        std::process::exit(hook::command(&args[1..]));
+    }
+
+    let (out, out_rx) = mpsc::unbounded_channel::<Event>();
+    let writer = spawn_output_writer(out_rx);
+
+    let bridge = Bridge::new(out);
+    bridge.usage.announce(bridge.out()).await;
+    if let Ok(cwd) = std::env::current_dir() {
+        bridge.recover_worktree(&cwd).await;
+    }
 
     let stdin = io::stdin();
-    let mut stdout = io::stdout();
     let reader = BufReader::new(stdin);
     let mut lines = reader.lines();
 
-    while let Ok(Some(line)) = lines.next_line().await {
+    let signalled = shutdown_signal();
+    tokio::pin!(signalled);
+
+    let exit_code = loop {
+        let line = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => line,
+                // Neovim has gone away; nobody is left to read the output.
+                Ok(None) | Err(_) => break 0,
+            },
+            code = &mut signalled => break code,
+        };
         if line.is_empty() {
             continue;
         }
 
-        let request: Request = match serde_json::from_str(&line) {
+        let Envelope { id, request } = match serde_json::from_str(&line) {
             Ok(r) => r,
             Err(e) => {
-                let err = Event::Error {
-                    message: format!("Invalid request: {}", e),
-                };
-                let _ = stdout.write_all(err.to_json_line().as_bytes()).await;
-                let _ = stdout.flush().await;
+                let err =
+                    BridgeError::new(ErrorCode::InvalidRequest, format!("Invalid request: {}", e));
+                bridge.emit(err.into_event(None));
                 continue;
             }
         };
 
         match request {
             Request::Chat(params) => {
-                {
-                    let mut proc = active_process.lock().await;
-                    if let Some(ref mut p) = *proc {
-                        let _ = p.abort().await;
-                    }
-                    *proc = None;
-                }
-
-                let (tx, rx) = mpsc::unbounded_channel::<Event>();
-
-                let proc_result =
-                    claude::ClaudeProcess::spawn(&params, None, session.clone(), tx).await;
-
-                match proc_result {
-                    Ok(process) => {
-                        *active_process.lock().await = Some(process);
-                        let handle = spawn_event_forwarder(rx, active_process.clone());
-                        *active_forwarder.lock().await = Some(handle);
-                    }
-                    Err(e) => {
-                        let err = Event::Error { message: e };
-                        let _ = stdout.write_all(err.to_json_line().as_bytes()).await;
-                        let _ = stdout.flush().await;
-                    }
-                }
+                bridge.submit(params, SessionTarget::New, id).await;
             }
 
             Request::Resume(params) => {
-                {
-                    let mut proc = active_process.lock().await;
-                    if let Some(ref mut p) = *proc {
-                        let _ = p.abort().await;
-                    }
-                    *proc = None;
-                }
-
-                let chat_params = protocol::ChatParams {
+                let chat_params = ChatParams {
                     prompt: String::new(),
                     cwd: params.cwd,
-                    context: None,
-                    model: None,
-                    allowed_tools: None,
-                    append_system_prompt: None,
-                    permission_mode: None,
+                    on_busy: Some(BusyBehavior::Replace),
+                    ..Default::default()
                 };
 
-                let (tx, rx) = mpsc::unbounded_channel::<Event>();
-                let proc_result = claude::ClaudeProcess::spawn(
-                    &chat_params,
-                    Some(params.session_id),
-                    session.clone(),
-                    tx,
-                )
-                .await;
-
-                match proc_result {
-                    Ok(process) => {
-                        *active_process.lock().await = Some(process);
-                        let handle = spawn_event_forwarder(rx, active_process.clone());
-                        *active_forwarder.lock().await = Some(handle);
-                    }
-                    Err(e) => {
-                        let err = Event::Error { message: e };
-                        let _ = stdout.write_all(err.to_json_line().as_bytes()).await;
-                        let _ = stdout.flush().await;
-                    }
-                }
+                bridge
+                    .submit(chat_params, SessionTarget::Resume(params.session_id), id)
+                    .await;
             }
 
             Request::Continue(params) => {
-                let sid = session.get_session_id().await;
-                if let Some(session_id) = sid {
-                    let chat_params = protocol::ChatParams {
-                        prompt: params.prompt,
-                        cwd: None,
-                        context: params.context,
-                        model: None,
-                        allowed_tools: None,
-                        append_system_prompt: None,
-                        permission_mode: None,
-                    };
-
-                    let (tx, rx) = mpsc::unbounded_channel::<Event>();
-                    let proc_result = claude::ClaudeProcess::spawn(
-                        &chat_params,
-                        Some(session_id),
-                        session.clone(),
-                        tx,
-                    )
-                    .await;
+                let chat_params = ChatParams {
+                    prompt: params.prompt,
+                    profile: params.profile,
+                    context: params.context,
+                    context_providers: params.context_providers,
+                    model: params.model,
+                    model_fallbacks: params.model_fallbacks,
+                    retry: params.retry,
+                    on_busy: params.on_busy,
+                    timeouts: params.timeouts,
+                    heartbeat_ms: params.heartbeat_ms,
+                    limits: params.limits,
+                    launch: params.launch,
+                    ..Default::default()
+                };
 
-                    match proc_result {
-                        Ok(process) => {
-                            *active_process.lock().await = Some(process);
-                            let handle = spawn_event_forwarder(rx, active_process.clone());
-                            *active_forwarder.lock().await = Some(handle);
-                        }
-                        Err(e) => {
-                            let err = Event::Error { message: e };
-                            let _ = stdout.write_all(err.to_json_line().as_bytes()).await;
-                            let _ = stdout.flush().await;
-                        }
-                    }
-                } else {
-                    let err = Event::Error {
-                        message: "No active session to continue".to_string(),
-                    };
-                    let _ = stdout.write_all(err.to_json_line().as_bytes()).await;
-                    let _ = stdout.flush().await;
-                }
+                bridge.submit(chat_params, SessionTarget::Latest, id).await;
             }
 
             Request::Abort => {
-                let mut proc = active_process.lock().await;
-                if let Some(ref mut p) = *proc {
-                    let _ = p.abort().await;
-                    let done = Event::Done;
-                    let _ = stdout.write_all(done.to_json_line().as_bytes()).await;
-                    let _ = stdout.flush().await;
+                bridge.clear_queue().await;
+                if bridge.abort_active().await {
+                    bridge.emit(Event::Done);
                 }
-                *proc = None;
             }
 
             Request::Status => {
-                let state = session.get_state().await;
-                let evt = Event::Status {
-                    active: state.active,
-                    session_id: state.session_id,
-                };
-                let _ = stdout.write_all(evt.to_json_line().as_bytes()).await;
-                let _ = stdout.flush().await;
+                let status = bridge.status().await;
+                bridge.emit(status);
+            }
+
+            Request::Steer(params) => {
+                bridge.steer(params.text, id).await;
+            }
+
+            Request::Ping => {
+                bridge.emit(Event::Pong {
+                    uptime_ms: bridge.uptime_ms(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                });
+            }
+
+            Request::Configure(params) => {
+                bridge.configure(params).await;
+            }
+
+            Request::Doctor => {
+                bridge.doctor().await;
+            }
+
+            Request::ListCheckpoints(params) => {
+                bridge.list_checkpoints(params.session_id, id).await;
+            }
+
+            Request::DeleteCheckpoints(params) => {
+                bridge.delete_checkpoints(params.session_id, id).await;
+            }
+
+            Request::Rollback(params) => {
+                bridge.rollback(params, id).await;
+            }
+
+            Request::BufferState(params) => {
+                bridge.buffers.update(params).await;
+            }
+
+            Request::Merge(params) => {
+                match bridge.buffers.merge(&params.tool_id, &params.content).await {
+                    Ok(event) => bridge.emit(event),
+                    Err(err) => bridge.emit(err.into_event(id)),
+                }
+            }
+
+            Request::MergeWorktree => {
+                bridge.close_worktree(true, id).await;
+            }
+
+            Request::DiscardWorktree => {
+                bridge.close_worktree(false, id).await;
+            }
+
+            Request::ShadowLog(params) => {
+                bridge.shadow_log(params, id).await;
+            }
+
+            Request::ListQueue => {
+                let items = bridge.list_queue().await;
+                bridge.emit(Event::Queue { items });
+            }
+
+            Request::CancelQueued(params) => {
+                if !bridge.cancel_queued(params.queue_id).await {
+                    let err = BridgeError::new(
+                        ErrorCode::InvalidRequest,
+                        format!("No queued prompt with id {}", params.queue_id),
+                    );
+                    bridge.emit(err.into_event(id));
+                }
             }
         }
-    }
+    };
 
-    let handle = active_forwarder.lock().await.take();
-    if let Some(h) = handle {
-        let _ = h.await;
-    }
+    bridge.shutdown().await;
+    drop(bridge);
+    // Dropping the bridge closes the output channel once in-flight tasks
+    // finish; don't let a straggler hold the exit up.
+    let _ = tokio::time::timeout(FLUSH_TIMEOUT, writer).await;
+    std::process::exit(exit_code);
 }
@@ -0,0 +1,294 @@
+use crate::changeset::walk;
+use crate::context::truncate;
+use crate::watcher::Ignores;
+use ignore::overrides::OverrideBuilder;
+use std::path::{Path, PathBuf};
+
+/// Total file contents inlined for one prompt.
+const MAX_BYTES: usize = 256 * 1024;
+/// Files a single glob or directory mention may pull in.
+const MAX_MATCHES: usize = 200;
+
+/// An `@` token in a prompt: a path or glob, optionally with a line range.
+#[derive(Debug, PartialEq, Eq)]
+struct Mention<'a> {
+    token: &'a str,
+    pattern: &'a str,
+    lines: Option<(usize, usize)>,
+}
+
+/// The files a prompt mentions, rendered for its context block.
+pub struct Expansion {
+    pub context: Option<String>,
+    /// Tokens that matched nothing, as written.
+    pub unresolved: Vec<String>,
+    /// Tokens naming something that is not inlined, with the reason.
+    pub refused: Vec<String>,
+}
+
+/// Resolves the `@path`, `@glob` and `@path:10-40` mentions in `prompt`
+/// against `cwd` and inlines the files. Nothing outside the project (the
+/// repository, or `cwd` outside one) or excluded by .gitignore is read.
+/// Blocking; run it off the async workers.
+pub fn expand(prompt: &str, cwd: &Path) -> Expansion {
+    let cwd = &cwd.canonicalize().unwrap_or_else(|_| cwd.to_path_buf());
+    let project = cwd
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(cwd);
+    let mut sections = Vec::new();
+    let mut unresolved = Vec::new();
+    let mut refused = Vec::new();
+    let mut budget = MAX_BYTES;
+    let mut omitted = 0;
+    for mention in parse(prompt) {
+        let files = match resolve(cwd, project, mention.pattern) {
+            Ok(files) => files,
+            Err(reason) => {
+                refused.push(format!("{} {}", mention.token, reason));
+                continue;
+            }
+        };
+        let mut found = false;
+        for file in files {
+            let Ok(bytes) = std::fs::read(&file) else {
+                continue;
+            };
+            let Some(body) = excerpt(&bytes, mention.lines) else {
+                continue;
+            };
+            found = true;
+            if budget == 0 {
+                omitted += 1;
+                continue;
+            }
+            let body = truncate(&body, budget);
+            budget = budget.saturating_sub(body.len());
+            sections.push(section(cwd, &file, mention.lines, &body));
+        }
+        if !found {
+            unresolved.push(mention.token.to_string());
+        }
+    }
+    if omitted > 0 {
+        sections.push(format!(
+            "[{} more mentioned files omitted; size limit reached]",
+            omitted
+        ));
+    }
+    Expansion {
+        context: (!sections.is_empty()).then(|| sections.join("\n\n")),
+        unresolved,
+        refused,
+    }
+}
+
+/// `@` tokens at the start of the prompt or after whitespace, each once.
+/// Trailing punctuation is taken as part of the sentence.
+fn parse(prompt: &str) -> Vec<Mention<'_>> {
+    let mut mentions: Vec<Mention> = Vec::new();
+    for word in prompt.split_whitespace() {
+        let Some(rest) = word.strip_prefix('@') else {
+            continue;
+        };
+        let rest = rest.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '"', '\'']);
+        if rest.is_empty() {
+            continue;
+        }
+        let token = &word[..rest.len() + 1];
+        if mentions.iter().any(|m| m.token == token) {
+            continue;
+        }
+        let (pattern, lines) = match rest.rsplit_once(':').map(|(p, r)| (p, line_range(r))) {
+            Some((pattern, Some(lines))) if !pattern.is_empty() => (pattern, Some(lines)),
+            _ => (rest, None),
+        };
+        mentions.push(Mention {
+            token,
+            pattern,
+            lines,
+        });
+    }
+    mentions
+}
+
+/// `10-40` or a single line `12`, one-based and inclusive.
+fn line_range(spec: &str) -> Option<(usize, usize)> {
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None => {
+            let line = spec.parse().ok()?;
+            (line, line)
+        }
+    };
+    (start > 0 && end >= start).then_some((start, end))
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Files a mention names, sorted, or why they may not be read.
+fn resolve(cwd: &Path, project: &Path, pattern: &str) -> Result<Vec<PathBuf>, &'static str> {
+    // A glob is walked from its literal directory prefix and the rest
+    // matched against paths below it.
+    let (prefix, rest): (Vec<&str>, Vec<&str>) = {
+        let parts: Vec<&str> = pattern.split('/').collect();
+        let at = parts.iter().position(|p| is_glob(p)).unwrap_or(parts.len());
+        (parts[..at].to_vec(), parts[at..].to_vec())
+    };
+    let Ok(path) = cwd.join(prefix.join("/")).canonicalize() else {
+        return Ok(Vec::new());
+    };
+    if !path.starts_with(project) {
+        return Err("is outside the project");
+    }
+    if Ignores::within(&path, project).is_ignored(&path, path.is_dir()) {
+        return Err("is excluded by .gitignore");
+    }
+
+    if rest.is_empty() {
+        if path.is_file() {
+            return Ok(vec![path]);
+        }
+        if path.is_dir() {
+            return Ok(files_under(&path, |_| true));
+        }
+        return Ok(Vec::new());
+    }
+    let mut builder = OverrideBuilder::new(&path);
+    if builder.add(&format!("/{}", rest.join("/"))).is_err() {
+        return Ok(Vec::new());
+    }
+    let Ok(globs) = builder.build() else {
+        return Ok(Vec::new());
+    };
+    Ok(files_under(&path, |p| {
+        globs.matched(p, false).is_whitelist()
+    }))
+}
+
+fn files_under(root: &Path, keep: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = walk(root)
+        .flatten()
+        .filter(|e| e.file_type().is_some_and(|t| t.is_file()) && keep(e.path()))
+        .take(MAX_MATCHES)
+        .map(|e| e.into_path())
+        .collect();
+    files.sort();
+    files
+}
+
+/// The text of a file, or of its `lines`. `None` for binary files and
+/// ranges past the end.
+fn excerpt(bytes: &[u8], lines: Option<(usize, usize)>) -> Option<String> {
+    if bytes.contains(&0) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(bytes);
+    let Some((start, end)) = lines else {
+        return Some(text.into_owned());
+    };
+    let picked: Vec<&str> = text
+        .split_inclusive('\n')
+        .skip(start - 1)
+        .take(end - start + 1)
+        .collect();
+    (!picked.is_empty()).then(|| picked.concat())
+}
+
+fn section(cwd: &Path, file: &Path, lines: Option<(usize, usize)>, body: &str) -> String {
+    let name = file.strip_prefix(cwd).unwrap_or(file).display();
+    let lang = file.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let body = body.strip_suffix('\n').unwrap_or(body);
+    match lines {
+        Some((start, end)) => format!(
+            "File: {} (lines {}-{})\n```{}\n{}\n```",
+            name, start, end, lang, body
+        ),
+        None => format!("File: {}\n```{}\n{}\n```", name, lang, body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mentions() {
+        let mentions = parse("look at @src/a.rs:10-40, @lib/**/*.rs and @b.rs. mail me@x.com @");
+        assert_eq!(
+            mentions,
+            vec![
+                Mention {
+                    token: "@src/a.rs:10-40",
+                    pattern: "src/a.rs",
+                    lines: Some((10, 40)),
+                },
+                Mention {
+                    token: "@lib/**/*.rs",
+                    pattern: "lib/**/*.rs",
+                    lines: None,
+                },
+                Mention {
+                    token: "@b.rs",
+                    pattern: "b.rs",
+                    lines: None,
+                },
+            ]
+        );
+        assert_eq!(parse("@a.rs:7")[0].lines, Some((7, 7)));
+        assert_eq!(parse("@a.rs:0-3")[0].pattern, "a.rs:0-3");
+    }
+
+    #[test]
+    fn test_expand_inlines_files_and_reports_unresolved() {
+        let dir = std::env::temp_dir().join(format!("claudecode-mentions-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src/deep")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "gen.rs\n").unwrap();
+        std::fs::write(dir.join("src/a.rs"), "1\n2\n3\n4\n").unwrap();
+        std::fs::write(dir.join("src/deep/b.rs"), "fn b() {}\n").unwrap();
+        std::fs::write(dir.join("src/deep/gen.rs"), "generated\n").unwrap();
+        std::fs::write(dir.join("src/c.txt"), "not rust\n").unwrap();
+
+        let expansion = expand("see @src/a.rs:2-3 and @src/**/*.rs, not @missing.rs", &dir);
+        let context = expansion.context.unwrap();
+        assert!(context.contains("File: src/a.rs (lines 2-3)\n```rs\n2\n3\n```"));
+        assert!(context.contains("File: src/deep/b.rs\n```rs\nfn b() {}\n```"));
+        assert!(!context.contains("generated"));
+        assert!(!context.contains("not rust"));
+        assert_eq!(expansion.unresolved, vec!["@missing.rs"]);
+
+        let expansion = expand("@src/a.rs:9-12", &dir);
+        assert!(expansion.context.is_none());
+        assert_eq!(expansion.unresolved, vec!["@src/a.rs:9-12"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_expand_refuses_files_outside_project_or_ignored() {
+        let dir = std::env::temp_dir().join(format!("claudecode-refused-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let project = dir.join("project");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        std::fs::write(dir.join("secret.txt"), "outside\n").unwrap();
+        std::fs::write(project.join(".gitignore"), ".env\n").unwrap();
+        std::fs::write(project.join(".env"), "TOKEN=1\n").unwrap();
+        std::fs::write(project.join("src/a.rs"), "fn a() {}\n").unwrap();
+
+        let expansion = expand("@../secret.txt @.env @src/../src/a.rs @../*.txt", &project);
+        let context = expansion.context.unwrap();
+        assert!(context.contains("File: src/a.rs"));
+        assert!(!context.contains("outside") && !context.contains("TOKEN"));
+        assert_eq!(
+            expansion.refused,
+            vec![
+                "@../secret.txt is outside the project",
+                "@.env is excluded by .gitignore",
+                "@../*.txt is outside the project",
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
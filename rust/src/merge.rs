@@ -0,0 +1,206 @@
+use similar::{DiffTag, TextDiff};
+use std::ops::Range;
+
+const MARKER_OURS: &str = "<<<<<<< buffer\n";
+const MARKER_BASE: &str = "||||||| claude read\n";
+const MARKER_SEP: &str = "=======\n";
+const MARKER_THEIRS: &str = ">>>>>>> claude\n";
+
+/// The outcome of a three-way merge.
+pub struct Merged {
+    pub content: String,
+    /// Regions both sides changed differently, left as diff3-style
+    /// conflict markers in `content`.
+    pub conflicts: usize,
+}
+
+/// One side's replacement of base lines `base` with `lines`.
+struct Hunk<'a> {
+    base: Range<usize>,
+    lines: &'a [&'a str],
+}
+
+/// Merges the editor's `buffer` and Claude's `proposed` version, both
+/// derived from `base`, line by line. Changes to separate regions are
+/// combined; overlapping changes that differ become conflict regions.
+pub fn merge3(base: &str, buffer: &str, proposed: &str) -> Merged {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = buffer.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = proposed.split_inclusive('\n').collect();
+    let ours = hunks(&base_lines, &ours_lines);
+    let theirs = hunks(&base_lines, &theirs_lines);
+
+    let mut out = String::new();
+    let mut conflicts = 0;
+    let mut pos = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < ours.len() || j < theirs.len() {
+        // Start a region at whichever hunk comes first, then pull in every
+        // hunk from either side that overlaps it.
+        let take_ours =
+            j >= theirs.len() || (i < ours.len() && ours[i].base.start <= theirs[j].base.start);
+        let first = if take_ours { &ours[i] } else { &theirs[j] };
+        let mut region = first.base.clone();
+        let (oi, tj) = (i, j);
+        loop {
+            if i < ours.len() && overlaps(&ours[i].base, &region) {
+                region.end = region.end.max(ours[i].base.end);
+                i += 1;
+            } else if j < theirs.len() && overlaps(&theirs[j].base, &region) {
+                region.end = region.end.max(theirs[j].base.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        out.extend(base_lines[pos..region.start].iter().copied());
+        let ours_text = apply(&base_lines, &region, &ours[oi..i]);
+        let theirs_text = apply(&base_lines, &region, &theirs[tj..j]);
+        if oi == i {
+            out.push_str(&theirs_text);
+        } else if tj == j || ours_text == theirs_text {
+            out.push_str(&ours_text);
+        } else {
+            conflicts += 1;
+            out.push_str(MARKER_OURS);
+            push_section(&mut out, &ours_text);
+            out.push_str(MARKER_BASE);
+            push_section(&mut out, &base_lines[region.clone()].concat());
+            out.push_str(MARKER_SEP);
+            push_section(&mut out, &theirs_text);
+            out.push_str(MARKER_THEIRS);
+        }
+        pos = region.end;
+    }
+    out.extend(base_lines[pos..].iter().copied());
+    Merged {
+        content: out,
+        conflicts,
+    }
+}
+
+/// Whether `hunk` collides with `region`. Changes that only touch end to
+/// end merge cleanly; two insertions at the same point do not.
+fn overlaps(hunk: &Range<usize>, region: &Range<usize>) -> bool {
+    hunk.start < region.end || (hunk.start == region.end && region.is_empty() && hunk.is_empty())
+}
+
+/// Changed regions of `other` relative to `base`.
+fn hunks<'a>(base: &[&str], other: &'a [&'a str]) -> Vec<Hunk<'a>> {
+    let diff = TextDiff::from_slices(base, other);
+    let mut hunks: Vec<Hunk<'a>> = Vec::new();
+    let mut new_ranges: Vec<Range<usize>> = Vec::new();
+    for op in diff.ops() {
+        if op.tag() == DiffTag::Equal {
+            continue;
+        }
+        let (old, new) = (op.old_range(), op.new_range());
+        // Adjacent delete and insert ops make up one replacement.
+        if let (Some(last), Some(last_new)) = (hunks.last_mut(), new_ranges.last_mut()) {
+            if last.base.end == old.start && last_new.end == new.start {
+                last.base.end = old.end;
+                last_new.end = new.end;
+                last.lines = &other[last_new.clone()];
+                continue;
+            }
+        }
+        hunks.push(Hunk {
+            base: old,
+            lines: &other[new.clone()],
+        });
+        new_ranges.push(new);
+    }
+    hunks
+}
+
+/// Base lines in `region` with one side's hunks applied.
+fn apply(base: &[&str], region: &Range<usize>, hunks: &[Hunk]) -> String {
+    let mut text = String::new();
+    let mut pos = region.start;
+    for hunk in hunks {
+        text.extend(base[pos..hunk.base.start].iter().copied());
+        text.extend(hunk.lines.iter().copied());
+        pos = hunk.base.end;
+    }
+    text.extend(base[pos..region.end].iter().copied());
+    text
+}
+
+/// Appends a conflict section, keeping the marker after it on its own line.
+fn push_section(out: &mut String, text: &str) {
+    out.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disjoint_hunks_merge_cleanly() {
+        let base = "a\nb\nc\nd\ne\n";
+        let buffer = "A\nb\nc\nd\ne\n";
+        let proposed = "a\nb\nc\nd\nE\nf\n";
+        let merged = merge3(base, buffer, proposed);
+        assert_eq!(merged.content, "A\nb\nc\nd\nE\nf\n");
+        assert_eq!(merged.conflicts, 0);
+    }
+
+    #[test]
+    fn test_adjacent_hunks_merge_cleanly() {
+        let merged = merge3("a\nb\n", "A\nb\n", "a\nB\n");
+        assert_eq!(merged.content, "A\nB\n");
+        assert_eq!(merged.conflicts, 0);
+    }
+
+    #[test]
+    fn test_identical_changes_are_not_conflicts() {
+        let merged = merge3("a\nb\nc\n", "a\nX\nc\n", "a\nX\nc\n");
+        assert_eq!(merged.content, "a\nX\nc\n");
+        assert_eq!(merged.conflicts, 0);
+    }
+
+    #[test]
+    fn test_overlapping_hunks_conflict() {
+        let base = "a\nb\nc\n";
+        let buffer = "a\nmine\nc\n";
+        let proposed = "a\ntheirs\nc\n";
+        let merged = merge3(base, buffer, proposed);
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.content,
+            "a\n<<<<<<< buffer\nmine\n||||||| claude read\nb\n=======\ntheirs\n>>>>>>> claude\nc\n"
+        );
+    }
+
+    #[test]
+    fn test_overlap_spanning_several_hunks_is_one_conflict() {
+        let base = "1\n2\n3\n4\n5\n";
+        let buffer = "1\nx\n3\ny\n5\n";
+        let proposed = "1\nZ\nZ\nZ\n5\n";
+        let merged = merge3(base, buffer, proposed);
+        assert_eq!(merged.conflicts, 1);
+        assert!(merged
+            .content
+            .contains("<<<<<<< buffer\nx\n3\ny\n||||||| claude read\n2\n3\n4\n=======\nZ\nZ\nZ\n"));
+    }
+
+    #[test]
+    fn test_insertions_at_same_point_conflict() {
+        let merged = merge3("a\nb\n", "a\nmine\nb\n", "a\ntheirs\nb\n");
+        assert_eq!(merged.conflicts, 1);
+    }
+
+    #[test]
+    fn test_missing_final_newline_keeps_markers_on_own_lines() {
+        let merged = merge3("a", "b", "c");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.content,
+            "<<<<<<< buffer\nb\n||||||| claude read\na\n=======\nc\n>>>>>>> claude\n"
+        );
+    }
+}
@@ -1,4 +1,16 @@
+use crate::error::ErrorCode;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A request line as sent by the editor. The optional `id` is echoed back on
+/// any error raised while handling the request.
+#[derive(Debug, Deserialize)]
+pub struct Envelope {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub request: Request,
+}
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "method", content = "params")]
@@ -9,9 +21,23 @@ pub enum Request {
     Continue(ContinueParams),
     Abort,
     Status,
+    ListQueue,
+    CancelQueued(CancelQueuedParams),
+    Steer(SteerParams),
+    Ping,
+    Configure(ConfigureParams),
+    Doctor,
+    ListCheckpoints(ListCheckpointsParams),
+    DeleteCheckpoints(DeleteCheckpointsParams),
+    Rollback(RollbackParams),
+    BufferState(BufferStateParams),
+    Merge(MergeParams),
+    MergeWorktree,
+    DiscardWorktree,
+    ShadowLog(ShadowLogParams),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct ChatParams {
     pub prompt: String,
     #[serde(default)]
@@ -26,6 +52,70 @@ pub struct ChatParams {
     pub append_system_prompt: Option<String>,
     #[serde(default)]
     pub permission_mode: Option<String>,
+    #[serde(default)]
+    pub mcp_config: Option<String>,
+    /// Profile to run under; picked by `cwd` when unset.
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Models tried in order when the current one is overloaded or
+    /// unavailable before producing any output.
+    #[serde(default)]
+    pub model_fallbacks: Vec<String>,
+    #[serde(default)]
+    pub on_busy: Option<BusyBehavior>,
+    #[serde(default)]
+    pub timeouts: Option<TimeoutPolicy>,
+    #[serde(default)]
+    pub heartbeat_ms: Option<u64>,
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>,
+    #[serde(default)]
+    pub isolation: Option<Isolation>,
+    #[serde(default)]
+    pub context_providers: Vec<ContextRequest>,
+    #[serde(flatten)]
+    pub launch: LaunchOptions,
+}
+
+/// How the CLI is launched. Set bridge-wide with `configure`; fields given
+/// on a request take precedence.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LaunchOptions {
+    pub claude_path: Option<String>,
+    /// Variables to set; a `null` value removes the variable.
+    pub env: HashMap<String, Option<String>>,
+    /// When set, only these variables are inherited from the bridge.
+    pub env_passthrough: Option<Vec<String>>,
+}
+
+/// Bridge-wide defaults, replacing any earlier `configure`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ConfigureParams {
+    #[serde(flatten)]
+    pub launch: LaunchOptions,
+    pub profiles: HashMap<String, Profile>,
+    pub shadow_commits: bool,
+    /// Off by default: the snapshot walks and hashes the whole tree.
+    pub track_changes: bool,
+}
+
+/// A named account/config setup. Its settings apply where the request
+/// leaves them unset.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Project directories the profile is picked for; the deepest match
+    /// wins.
+    pub paths: Vec<String>,
+    /// Exported to the CLI as `CLAUDE_CONFIG_DIR`.
+    pub config_dir: Option<String>,
+    pub model: Option<String>,
+    pub allowed_tools: Option<Vec<String>>,
+    pub mcp_config: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,8 +128,242 @@ pub struct ResumeParams {
 #[derive(Debug, Deserialize)]
 pub struct ContinueParams {
     pub prompt: String,
+    /// Defaults to the profile of the session being continued.
+    #[serde(default)]
+    pub profile: Option<String>,
     #[serde(default)]
     pub context: Option<String>,
+    #[serde(default)]
+    pub context_providers: Vec<ContextRequest>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub model_fallbacks: Vec<String>,
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    #[serde(default)]
+    pub on_busy: Option<BusyBehavior>,
+    #[serde(default)]
+    pub timeouts: Option<TimeoutPolicy>,
+    /// Interval for `heartbeat` events while the turn runs; off when unset.
+    #[serde(default)]
+    pub heartbeat_ms: Option<u64>,
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>,
+    #[serde(flatten)]
+    pub launch: LaunchOptions,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SteerParams {
+    pub text: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ListCheckpointsParams {
+    pub session_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DeleteCheckpointsParams {
+    pub session_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RollbackParams {
+    /// Checkpoint turn to undo, along with every turn after it.
+    pub turn: u32,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Restore files even where they changed after the turn.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// The editor's view of a file, pushed when a buffer is read, written or
+/// changes its modified flag.
+#[derive(Debug, Deserialize)]
+pub struct BufferStateParams {
+    pub path: String,
+    /// Hex SHA-256 of the buffer as it would be written; absent once the
+    /// buffer is closed.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    #[serde(default)]
+    pub modified: bool,
+}
+
+/// Defaults to the bridge's working directory and every session.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ShadowLogParams {
+    pub cwd: Option<String>,
+    pub session_id: Option<String>,
+}
+
+/// Asks for a proposed edit merged into the editor's copy of the file.
+#[derive(Debug, Deserialize)]
+pub struct MergeParams {
+    pub tool_id: String,
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelQueuedParams {
+    pub queue_id: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Isolation {
+    #[default]
+    None,
+    /// A git worktree on a new branch, kept until merged or discarded.
+    Worktree,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ContextRequest {
+    #[serde(flatten)]
+    pub provider: ContextProvider,
+    /// Output beyond this is cut; defaults to 32 KiB.
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "name")]
+pub enum ContextProvider {
+    /// Working copy changes, or the index's with `staged`.
+    #[serde(rename = "git_diff")]
+    Diff {
+        #[serde(default)]
+        staged: bool,
+    },
+    #[serde(rename = "git_diff_base")]
+    DiffBase {
+        #[serde(rename = "ref")]
+        base: String,
+    },
+    /// The last `count` commits; 10 when unset.
+    #[serde(rename = "git_log")]
+    Log {
+        #[serde(default)]
+        count: Option<u32>,
+    },
+    #[serde(rename = "git_blame")]
+    Blame {
+        path: String,
+        start_line: u32,
+        end_line: u32,
+    },
+    #[serde(rename = "git_status")]
+    Status,
+}
+
+/// What to do with a prompt that arrives while a turn is running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusyBehavior {
+    #[default]
+    Queue,
+    Replace,
+    Reject,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueItem {
+    pub queue_id: u64,
+    pub position: usize,
+    pub prompt: String,
+    pub request_id: Option<String>,
+}
+
+/// Watchdog limits for a turn; a `None` limit is disabled. Idle time
+/// counts from the last event the CLI produced, tool calls excluded.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimeoutPolicy {
+    /// Interval between `stalled` warnings while the CLI is silent.
+    pub stall_warning_ms: Option<u64>,
+    /// Kills a silent CLI. Off unless set.
+    pub idle_timeout_ms: Option<u64>,
+    /// Caps the whole turn, retries and fallbacks included.
+    pub turn_timeout_ms: Option<u64>,
+}
+
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        Self {
+            stall_warning_ms: Some(60_000),
+            idle_timeout_ms: None,
+            turn_timeout_ms: None,
+        }
+    }
+}
+
+/// Limits applied to the CLI process, and inherited by everything it
+/// starts; a `None` limit is left as the bridge's own.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// RLIMIT_CPU, in seconds of CPU time per process.
+    pub cpu_seconds: Option<u64>,
+    /// RLIMIT_AS, in megabytes of address space per process.
+    pub address_space_mb: Option<u64>,
+    pub nice: Option<i32>,
+}
+
+/// How a turn that fails with a transient error is retried. Retries resume
+/// the failed attempt's session so no context is lost.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts including the first; 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub retry_on: Vec<ErrorCode>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+            // A CLI that crashes tends to crash again, and a rerun repeats
+            // the tool calls it made; opt in to `cli_exit_nonzero`.
+            retry_on: vec![ErrorCode::Overloaded, ErrorCode::NetworkError],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn should_retry(&self, code: ErrorCode, attempt: u32) -> bool {
+        attempt < self.max_attempts && self.retry_on.contains(&code)
+    }
+
+    /// Exponential backoff before attempt `attempt + 1`, never shorter than a
+    /// server-provided `retry_after`.
+    pub fn delay_ms(&self, attempt: u32, retry_after: Option<u64>) -> u64 {
+        let exp = attempt.saturating_sub(1).min(20);
+        let backoff = self
+            .initial_delay_ms
+            .saturating_mul(1u64 << exp)
+            .min(self.max_delay_ms);
+        backoff.max(retry_after.unwrap_or(0).saturating_mul(1000))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Log,
+    Warning,
+    Error,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -76,14 +400,237 @@ pub enum Event {
     },
     Done,
     Error {
+        code: ErrorCode,
         message: String,
+        fatal: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        retry_after: Option<u64>,
+        request_id: Option<String>,
     },
     Status {
         active: bool,
         session_id: Option<String>,
+        model: Option<String>,
+        cwd: Option<String>,
+        permission_mode: Option<String>,
+        /// Unix seconds at which the running turn started.
+        turn_started_at: Option<u64>,
+        elapsed_ms: Option<u64>,
+        output_tokens: u64,
+        tool_calls: u32,
+        current_tool: Option<String>,
+        queue_length: usize,
+        pid: Option<u32>,
+        total_cost_usd: f64,
+        profile: Option<String>,
+        worktree: Option<String>,
+    },
+    Log {
+        severity: Severity,
+        message: String,
+    },
+    UsageLimit {
+        reset_at: Option<u64>,
+        limited: bool,
+    },
+    Retrying {
+        attempt: u32,
+        delay_ms: u64,
+        reason: String,
+    },
+    ModelFallback {
+        from: Option<String>,
+        to: String,
+        reason: String,
+    },
+    Queued {
+        queue_id: u64,
+        position: usize,
+        request_id: Option<String>,
+    },
+    QueueStarted {
+        queue_id: u64,
+        request_id: Option<String>,
+    },
+    QueueCancelled {
+        queue_id: u64,
+    },
+    Queue {
+        items: Vec<QueueItem>,
+    },
+    /// `delivered` is false while a steering message waits for the next turn
+    /// boundary; a second ack with `delivered: true` follows once it is sent.
+    SteerAck {
+        steer_id: u64,
+        delivered: bool,
+        request_id: Option<String>,
+    },
+    Stalled {
+        idle_ms: u64,
+    },
+    Pong {
+        uptime_ms: u64,
+        version: String,
+    },
+    Heartbeat {
+        elapsed_ms: u64,
+        output_tokens: u64,
+        current_tool: Option<String>,
+    },
+    Doctor {
+        checks: Vec<DoctorCheck>,
+    },
+    Checkpoints {
+        session_id: String,
+        checkpoints: Vec<CheckpointInfo>,
+    },
+    RollbackResult {
+        session_id: String,
+        turn: u32,
+        restored: Vec<String>,
+        /// Files left alone because they changed after the turn wrote them.
+        conflicts: Vec<String>,
+    },
+    /// What a file-modifying tool call will do to its file, computed from
+    /// the file as it was when the call was seen.
+    ProposedChange {
+        tool_id: String,
+        path: String,
+        old_hash: Option<String>,
+        new_hash: String,
+        diff: String,
+        hunks: Vec<DiffHunk>,
+        /// An `old_string` matched more than once and only the first was used.
+        ambiguous: bool,
+        /// An `old_string` (or notebook cell) was not found.
+        unmatched: bool,
+    },
+    /// A file-modifying tool call targets a file the editor holds in a
+    /// state Claude hasn't seen.
+    Conflict {
+        tool_id: String,
+        path: String,
+        reason: ConflictReason,
+        buffer_hash: String,
+        /// Hash of the contents Claude last read or wrote, if known.
+        read_hash: Option<String>,
+    },
+    MergeResult {
+        tool_id: String,
+        path: String,
+        content: String,
+        /// Regions left as conflict markers in `content`.
+        conflicts: usize,
+    },
+    UnresolvedMentions {
+        mentions: Vec<String>,
+    },
+    /// Shadow commits of a repository, oldest first.
+    ShadowLog {
+        entries: Vec<ShadowEntry>,
+    },
+    Worktree {
+        path: String,
+        branch: String,
+        state: WorktreeState,
+    },
+    FileChanged {
+        path: String,
+        kind: ChangeKind,
+        /// The tool call running when the change was seen, if any.
+        by_tool_id: Option<String>,
+    },
+    /// Every file under the working directory that differs from how the
+    /// turn found it, whichever tool changed it.
+    Changeset {
+        files: Vec<FileChange>,
+        added: usize,
+        deleted: usize,
+        diff: String,
+        /// The diff was cut short, or the tree was too large to track whole.
+        truncated: bool,
     },
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowEntry {
+    pub commit: String,
+    pub created_at: u64,
+    pub session_id: String,
+    pub turn: u32,
+    pub prompt: String,
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckpointInfo {
+    pub turn: u32,
+    pub created_at: u64,
+    pub prompt: String,
+    pub files: Vec<String>,
+}
+
+/// One hunk of a unified diff. `lines` carry their ` `, `-` or `+` prefix.
+/// Starts are 1-based; a side with no lines starts at the line it follows.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorktreeState {
+    Created,
+    /// Left open by an earlier bridge and taken back.
+    Restored,
+    Merged,
+    Discarded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictReason {
+    Unsaved,
+    /// The file was saved with changes after Claude last read it.
+    StaleRead,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// One file in a `changeset`, relative to the working directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    pub added: usize,
+    pub deleted: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
 impl Event {
     pub fn to_json_line(&self) -> String {
         let mut s = serde_json::to_string(self).unwrap_or_else(|e| {
@@ -97,6 +644,7 @@ impl Event {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::BridgeError;
 
     #[test]
     fn test_deserialize_chat_request() {
@@ -246,13 +794,74 @@ mod tests {
 
     #[test]
     fn test_serialize_error_event() {
-        let evt = Event::Error {
-            message: "bad thing".to_string(),
-        };
+        let evt = BridgeError::new(ErrorCode::CliNotFound, "bad thing")
+            .into_event(Some("req-1".to_string()));
         let json = evt.to_json_line();
         let parsed: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
         assert_eq!(parsed["event"], "error");
+        assert_eq!(parsed["code"], "cli_not_found");
         assert_eq!(parsed["message"], "bad thing");
+        assert_eq!(parsed["fatal"], true);
+        assert_eq!(parsed["request_id"], "req-1");
+        assert!(parsed.get("retry_after").is_none());
+    }
+
+    #[test]
+    fn test_serialize_non_fatal_error_with_retry_after() {
+        let evt = BridgeError {
+            fatal: false,
+            retry_after: Some(30),
+            ..BridgeError::new(ErrorCode::RateLimited, "slow down")
+        }
+        .into_event(None);
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["code"], "rate_limited");
+        assert_eq!(parsed["fatal"], false);
+        assert_eq!(parsed["retry_after"], 30);
+        assert!(parsed["request_id"].is_null());
+    }
+
+    #[test]
+    fn test_error_code_from_cli_message() {
+        assert_eq!(
+            ErrorCode::from_cli_message("API Error: 529 Overloaded"),
+            ErrorCode::Overloaded
+        );
+        assert_eq!(
+            ErrorCode::from_cli_message("Claude AI usage limit reached"),
+            ErrorCode::RateLimited
+        );
+        assert_eq!(
+            ErrorCode::from_cli_message("Invalid API key · Please run /login"),
+            ErrorCode::NotAuthenticated
+        );
+        assert_eq!(
+            ErrorCode::from_cli_message("something else"),
+            ErrorCode::CliError
+        );
+        assert_eq!(
+            ErrorCode::from_cli_message("API Error: 429 {\"type\":\"error\"}"),
+            ErrorCode::RateLimited
+        );
+        // Digits inside other numbers are not status codes.
+        assert_eq!(
+            ErrorCode::from_cli_message("Tool failed at line 15291 on port 4012"),
+            ErrorCode::CliError
+        );
+        assert_eq!(ErrorCode::from_status(529), Some(ErrorCode::Overloaded));
+        assert_eq!(ErrorCode::from_status(500), None);
+    }
+
+    #[test]
+    fn test_deserialize_envelope_with_id() {
+        let json = r#"{"id":"r7","method":"chat","params":{"prompt":"hi"}}"#;
+        let env: Envelope = serde_json::from_str(json).unwrap();
+        assert_eq!(env.id, Some("r7".to_string()));
+        assert!(matches!(env.request, Request::Chat(_)));
+
+        let env: Envelope = serde_json::from_str(r#"{"method":"abort"}"#).unwrap();
+        assert!(env.id.is_none());
+        assert!(matches!(env.request, Request::Abort));
     }
 
     #[test]
@@ -260,20 +869,515 @@ mod tests {
         let evt = Event::Status {
             active: true,
             session_id: Some("s1".to_string()),
+            model: Some("opus".to_string()),
+            cwd: None,
+            permission_mode: None,
+            turn_started_at: Some(1_700_000_000),
+            elapsed_ms: Some(2500),
+            output_tokens: 120,
+            tool_calls: 2,
+            current_tool: None,
+            queue_length: 1,
+            pid: Some(4242),
+            total_cost_usd: 0.25,
+            profile: Some("work".to_string()),
+            worktree: None,
         };
         let json = evt.to_json_line();
         let parsed: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
         assert_eq!(parsed["event"], "status");
         assert_eq!(parsed["active"], true);
         assert_eq!(parsed["session_id"], "s1");
+        assert_eq!(parsed["queue_length"], 1);
+        assert_eq!(parsed["pid"], 4242);
+        assert_eq!(parsed["profile"], "work");
+        assert!(parsed["cwd"].is_null());
+    }
+
+    #[test]
+    fn test_serialize_log_event() {
+        let evt = Event::Log {
+            severity: Severity::Warning,
+            message: "deprecated flag".to_string(),
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "log");
+        assert_eq!(parsed["severity"], "warning");
+    }
+
+    #[test]
+    fn test_serialize_usage_limit_event() {
+        let evt = Event::UsageLimit {
+            reset_at: Some(1760000000),
+            limited: true,
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "usage_limit");
+        assert_eq!(parsed["reset_at"], 1760000000);
+        assert_eq!(parsed["limited"], true);
+    }
+
+    #[test]
+    fn test_deserialize_retry_policy() {
+        let json = r#"{"method":"chat","params":{"prompt":"hi","retry":{"max_attempts":5,"retry_on":["overloaded"]}}}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::Chat(params) => {
+                let policy = params.retry.unwrap();
+                assert_eq!(policy.max_attempts, 5);
+                assert_eq!(policy.initial_delay_ms, 1000);
+                assert_eq!(policy.retry_on, vec![ErrorCode::Overloaded]);
+            }
+            _ => panic!("expected Chat variant"),
+        }
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay_ms(1, None), 1000);
+        assert_eq!(policy.delay_ms(2, None), 2000);
+        assert_eq!(policy.delay_ms(10, None), 30_000);
+        assert_eq!(policy.delay_ms(1, Some(5)), 5000);
+        assert!(policy.should_retry(ErrorCode::Overloaded, 2));
+        assert!(!policy.should_retry(ErrorCode::Overloaded, 3));
+        assert!(!policy.should_retry(ErrorCode::NotAuthenticated, 1));
+        assert!(!policy.should_retry(ErrorCode::CliExitNonzero, 1));
+    }
+
+    #[test]
+    fn test_serialize_retrying_event() {
+        let evt = Event::Retrying {
+            attempt: 2,
+            delay_ms: 1000,
+            reason: "overloaded".to_string(),
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "retrying");
+        assert_eq!(parsed["attempt"], 2);
+        assert_eq!(parsed["delay_ms"], 1000);
+    }
+
+    #[test]
+    fn test_deserialize_model_fallbacks() {
+        let json = r#"{"method":"chat","params":{"prompt":"hi","model":"opus","model_fallbacks":["sonnet","haiku"]}}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::Chat(params) => {
+                assert_eq!(params.model_fallbacks, vec!["sonnet", "haiku"]);
+            }
+            _ => panic!("expected Chat variant"),
+        }
+
+        let json = r#"{"method":"continue","params":{"prompt":"next","model":"opus","model_fallbacks":["sonnet"]}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::Continue(params) => {
+                assert_eq!(params.model.as_deref(), Some("opus"));
+                assert_eq!(params.model_fallbacks, vec!["sonnet"]);
+            }
+            _ => panic!("expected Continue variant"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_model_fallback_event() {
+        let evt = Event::ModelFallback {
+            from: Some("opus".to_string()),
+            to: "sonnet".to_string(),
+            reason: "overloaded".to_string(),
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "model_fallback");
+        assert_eq!(parsed["from"], "opus");
+        assert_eq!(parsed["to"], "sonnet");
+    }
+
+    #[test]
+    fn test_deserialize_queue_requests() {
+        let json = r#"{"method":"chat","params":{"prompt":"hi","on_busy":"reject"}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::Chat(params) => assert_eq!(params.on_busy, Some(BusyBehavior::Reject)),
+            _ => panic!("expected Chat variant"),
+        }
+
+        let req: Request = serde_json::from_str(r#"{"method":"list_queue"}"#).unwrap();
+        assert!(matches!(req, Request::ListQueue));
+
+        let json = r#"{"method":"cancel_queued","params":{"queue_id":3}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::CancelQueued(params) => assert_eq!(params.queue_id, 3),
+            _ => panic!("expected CancelQueued variant"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_queue_events() {
+        let evt = Event::Queued {
+            queue_id: 4,
+            position: 2,
+            request_id: Some("r1".to_string()),
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "queued");
+        assert_eq!(parsed["position"], 2);
+
+        let evt = Event::Queue {
+            items: vec![QueueItem {
+                queue_id: 4,
+                position: 1,
+                prompt: "next".to_string(),
+                request_id: None,
+            }],
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "queue");
+        assert_eq!(parsed["items"][0]["prompt"], "next");
+    }
+
+    #[test]
+    fn test_deserialize_steer_request() {
+        let json = r#"{"method":"steer","params":{"text":"use the other API"}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::Steer(params) => assert_eq!(params.text, "use the other API"),
+            _ => panic!("expected Steer variant"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_timeout_policy() {
+        let json = r#"{"method":"continue","params":{"prompt":"go","timeouts":{"idle_timeout_ms":5000,"turn_timeout_ms":null}}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::Continue(params) => {
+                let limits = params.timeouts.unwrap();
+                assert_eq!(limits.idle_timeout_ms, Some(5000));
+                assert_eq!(limits.stall_warning_ms, Some(60_000));
+                assert!(limits.turn_timeout_ms.is_none());
+                assert!(TimeoutPolicy::default().idle_timeout_ms.is_none());
+            }
+            _ => panic!("expected Continue variant"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_ping_request() {
+        let req: Request = serde_json::from_str(r#"{"method":"ping"}"#).unwrap();
+        assert!(matches!(req, Request::Ping));
+    }
+
+    #[test]
+    fn test_serialize_heartbeat_event() {
+        let evt = Event::Heartbeat {
+            elapsed_ms: 1500,
+            output_tokens: 42,
+            current_tool: Some("Bash".to_string()),
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "heartbeat");
+        assert_eq!(parsed["output_tokens"], 42);
+        assert_eq!(parsed["current_tool"], "Bash");
+    }
+
+    #[test]
+    fn test_deserialize_resource_limits() {
+        let json = r#"{"prompt":"hi","limits":{"cpu_seconds":600,"nice":10}}"#;
+        let params: ChatParams = serde_json::from_str(json).unwrap();
+        let limits = params.limits.unwrap();
+        assert_eq!(limits.cpu_seconds, Some(600));
+        assert_eq!(limits.address_space_mb, None);
+        assert_eq!(limits.nice, Some(10));
+    }
+
+    #[test]
+    fn test_deserialize_launch_options() {
+        let json = r#"{"prompt":"hi","claude_path":"/opt/claude","env":{"CLAUDE_CONFIG_DIR":"/w","AWS_SECRET_ACCESS_KEY":null},"env_passthrough":["PATH","HOME"]}"#;
+        let params: ChatParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.launch.claude_path.as_deref(), Some("/opt/claude"));
+        assert_eq!(
+            params.launch.env["CLAUDE_CONFIG_DIR"].as_deref(),
+            Some("/w")
+        );
+        assert_eq!(params.launch.env["AWS_SECRET_ACCESS_KEY"], None);
+        assert_eq!(params.launch.env_passthrough.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_deserialize_configure_request() {
+        let json = r#"{"method":"configure","params":{"env":{"FOO":"1"}}}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::Configure(p) => assert_eq!(p.launch.env["FOO"].as_deref(), Some("1")),
+            _ => panic!("Expected Configure"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_configure_profiles() {
+        let json = r#"{"method":"configure","params":{"profiles":{"work":{"paths":["~/work"],"config_dir":"~/.claude-work","model":"opus"}}}}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::Configure(p) => {
+                let work = &p.profiles["work"];
+                assert_eq!(work.paths, vec!["~/work".to_string()]);
+                assert_eq!(work.config_dir.as_deref(), Some("~/.claude-work"));
+                assert!(work.allowed_tools.is_none());
+            }
+            _ => panic!("Expected Configure"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_doctor_event() {
+        let req: Request = serde_json::from_str(r#"{"method":"doctor"}"#).unwrap();
+        assert!(matches!(req, Request::Doctor));
+
+        let evt = Event::Doctor {
+            checks: vec![DoctorCheck {
+                name: "version".to_string(),
+                status: CheckStatus::Warn,
+                message: "claude 3.0.0 is newer than tested".to_string(),
+            }],
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "doctor");
+        assert_eq!(parsed["checks"][0]["name"], "version");
+        assert_eq!(parsed["checks"][0]["status"], "warn");
+    }
+
+    #[test]
+    fn test_serialize_proposed_change_event() {
+        let evt = Event::ProposedChange {
+            tool_id: "t1".to_string(),
+            path: "/tmp/a.txt".to_string(),
+            old_hash: None,
+            new_hash: "abc".to_string(),
+            diff: "@@ -0,0 +1 @@\n+hi\n".to_string(),
+            hunks: vec![DiffHunk {
+                old_start: 1,
+                old_lines: 0,
+                new_start: 1,
+                new_lines: 1,
+                lines: vec!["+hi".to_string()],
+            }],
+            ambiguous: false,
+            unmatched: false,
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "proposed_change");
+        assert!(parsed["old_hash"].is_null());
+        assert_eq!(parsed["hunks"][0]["new_lines"], 1);
+        assert_eq!(parsed["hunks"][0]["lines"][0], "+hi");
+    }
+
+    #[test]
+    fn test_deserialize_rollback_request() {
+        let json = r#"{"method":"rollback","params":{"turn":3}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::Rollback(p) => {
+                assert_eq!(p.turn, 3);
+                assert!(p.session_id.is_none());
+                assert!(!p.force);
+            }
+            _ => panic!("Expected Rollback"),
+        }
+
+        let json = r#"{"method":"list_checkpoints","params":{}}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert!(matches!(req, Request::ListCheckpoints(_)));
+
+        let json = r#"{"method":"delete_checkpoints","params":{"session_id":"s1"}}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert!(
+            matches!(req, Request::DeleteCheckpoints(p) if p.session_id.as_deref() == Some("s1"))
+        );
+    }
+
+    #[test]
+    fn test_serialize_rollback_result_event() {
+        let evt = Event::RollbackResult {
+            session_id: "s1".to_string(),
+            turn: 2,
+            restored: vec!["/p/a.rs".to_string()],
+            conflicts: vec![],
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "rollback_result");
+        assert_eq!(parsed["restored"][0], "/p/a.rs");
+    }
+
+    #[test]
+    fn test_serialize_changeset_event() {
+        let evt = Event::Changeset {
+            files: vec![FileChange {
+                path: "src/a.rs".to_string(),
+                kind: ChangeKind::Created,
+                added: 2,
+                deleted: 0,
+            }],
+            added: 2,
+            deleted: 0,
+            diff: "--- a/src/a.rs\n+++ b/src/a.rs\n".to_string(),
+            truncated: false,
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "changeset");
+        assert_eq!(parsed["files"][0]["kind"], "created");
+        assert_eq!(parsed["added"], 2);
+    }
+
+    #[test]
+    fn test_serialize_file_changed_event() {
+        let evt = Event::FileChanged {
+            path: "/p/a.rs".to_string(),
+            kind: ChangeKind::Deleted,
+            by_tool_id: None,
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "file_changed");
+        assert_eq!(parsed["kind"], "deleted");
+        assert!(parsed["by_tool_id"].is_null());
+    }
+
+    #[test]
+    fn test_deserialize_buffer_state_request() {
+        let json = r#"{"method":"buffer_state","params":{"path":"/p/a.rs","content_hash":"ab","modified":true}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::BufferState(p) => {
+                assert_eq!(p.content_hash.as_deref(), Some("ab"));
+                assert!(p.modified);
+            }
+            _ => panic!("Expected BufferState"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_conflict_event() {
+        let evt = Event::Conflict {
+            tool_id: "t1".to_string(),
+            path: "/p/a.rs".to_string(),
+            reason: ConflictReason::StaleRead,
+            buffer_hash: "ab".to_string(),
+            read_hash: Some("cd".to_string()),
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "conflict");
+        assert_eq!(parsed["reason"], "stale_read");
+    }
+
+    #[test]
+    fn test_deserialize_merge_request() {
+        let json = r#"{"method":"merge","params":{"tool_id":"t1","content":"a\n"}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::Merge(p) => {
+                assert_eq!(p.tool_id, "t1");
+                assert_eq!(p.content, "a\n");
+            }
+            _ => panic!("Expected Merge"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_context_providers() {
+        let json = r#"{"method":"chat","params":{"prompt":"hi","context_providers":[
+            {"name":"git_diff","staged":true},
+            {"name":"git_diff_base","ref":"main","max_bytes":100},
+            {"name":"git_log"},
+            {"name":"git_blame","path":"a.rs","start_line":3,"end_line":9},
+            {"name":"git_status"}
+        ]}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::Chat(p) => {
+                let providers: Vec<_> = p.context_providers.iter().map(|c| &c.provider).collect();
+                assert_eq!(
+                    providers,
+                    vec![
+                        &ContextProvider::Diff { staged: true },
+                        &ContextProvider::DiffBase {
+                            base: "main".to_string()
+                        },
+                        &ContextProvider::Log { count: None },
+                        &ContextProvider::Blame {
+                            path: "a.rs".to_string(),
+                            start_line: 3,
+                            end_line: 9
+                        },
+                        &ContextProvider::Status,
+                    ]
+                );
+                assert_eq!(p.context_providers[1].max_bytes, Some(100));
+            }
+            _ => panic!("Expected Chat"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_worktree_requests() {
+        let json = r#"{"method":"chat","params":{"prompt":"hi","isolation":"worktree"}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::Chat(p) => assert_eq!(p.isolation, Some(Isolation::Worktree)),
+            _ => panic!("Expected Chat"),
+        }
+        let req: Request = serde_json::from_str(r#"{"method":"merge_worktree"}"#).unwrap();
+        assert!(matches!(req, Request::MergeWorktree));
+        let req: Request = serde_json::from_str(r#"{"method":"discard_worktree"}"#).unwrap();
+        assert!(matches!(req, Request::DiscardWorktree));
+    }
+
+    #[test]
+    fn test_serialize_worktree_event() {
+        let evt = Event::Worktree {
+            path: "/state/worktrees/app-1".to_string(),
+            branch: "claudecode/app-1".to_string(),
+            state: WorktreeState::Created,
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "worktree");
+        assert_eq!(parsed["state"], "created");
+    }
+
+    #[test]
+    fn test_shadow_log_request_and_event() {
+        let json = r#"{"method":"shadow_log","params":{"session_id":"s1"}}"#;
+        match serde_json::from_str::<Request>(json).unwrap() {
+            Request::ShadowLog(p) => {
+                assert_eq!(p.session_id.as_deref(), Some("s1"));
+                assert!(p.cwd.is_none());
+            }
+            _ => panic!("Expected ShadowLog"),
+        }
+
+        let evt = Event::ShadowLog {
+            entries: vec![ShadowEntry {
+                commit: "abc".to_string(),
+                created_at: 1,
+                session_id: "s1".to_string(),
+                turn: 3,
+                prompt: "fix it".to_string(),
+                cost_usd: 0.5,
+            }],
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "shadow_log");
+        assert_eq!(parsed["entries"][0]["turn"], 3);
+    }
+
+    #[test]
+    fn test_serialize_unresolved_mentions() {
+        let evt = Event::UnresolvedMentions {
+            mentions: vec!["@missing.rs".to_string()],
+        };
+        let parsed: serde_json::Value = serde_json::from_str(evt.to_json_line().trim()).unwrap();
+        assert_eq!(parsed["event"], "unresolved_mentions");
+        assert_eq!(parsed["mentions"][0], "@missing.rs");
     }
 
     #[test]
     fn test_to_json_line_ends_with_newline() {
         let events = vec![
             Event::Done,
-            Event::Error { message: "x".to_string() },
-            Event::TextChunk { text: "y".to_string() },
+            BridgeError::new(ErrorCode::Stderr, "x").into_event(None),
+            Event::TextChunk {
+                text: "y".to_string(),
+            },
         ];
         for evt in events {
             let line = evt.to_json_line();
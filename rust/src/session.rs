@@ -1,11 +1,28 @@
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
 
+/// Live progress of the turn in flight; reset when a turn starts.
+#[derive(Debug, Clone, Default)]
+pub struct TurnStats {
+    pub started: Option<Instant>,
+    pub output_tokens: u64,
+    pub tool_calls: u32,
+    pub current_tool: Option<String>,
+    pub current_tool_id: Option<String>,
+    pub cost_usd: f64,
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionState {
     pub session_id: Option<String>,
     pub active: bool,
     pub model: Option<String>,
+    pub turn: TurnStats,
+    /// Spend across every turn of the current session.
+    pub total_cost_usd: f64,
+    /// Profile of the most recently started turn.
+    pub profile: Option<String>,
 }
 
 impl SessionState {
@@ -14,6 +31,9 @@ impl SessionState {
             session_id: None,
             active: false,
             model: None,
+            turn: TurnStats::default(),
+            total_cost_usd: 0.0,
+            profile: None,
         }
     }
 }
@@ -32,6 +52,9 @@ impl SessionManager {
 
     pub async fn set_active(&self, session_id: String, model: String) {
         let mut state = self.state.lock().await;
+        if state.session_id.as_deref() != Some(session_id.as_str()) {
+            state.total_cost_usd = 0.0;
+        }
         state.session_id = Some(session_id);
         state.model = Some(model);
         state.active = true;
@@ -42,6 +65,44 @@ impl SessionManager {
         state.active = false;
     }
 
+    pub async fn begin_turn(&self) {
+        self.state.lock().await.turn = TurnStats {
+            started: Some(Instant::now()),
+            ..TurnStats::default()
+        };
+    }
+
+    pub async fn set_profile(&self, profile: Option<String>) {
+        self.state.lock().await.profile = profile;
+    }
+
+    pub async fn set_output_tokens(&self, tokens: u64) {
+        self.state.lock().await.turn.output_tokens = tokens;
+    }
+
+    pub async fn add_cost(&self, usd: f64) {
+        let mut state = self.state.lock().await;
+        state.total_cost_usd += usd;
+        state.turn.cost_usd += usd;
+    }
+
+    pub async fn tool_started(&self, tool: String, id: String) {
+        let mut state = self.state.lock().await;
+        state.turn.tool_calls += 1;
+        state.turn.current_tool = Some(tool);
+        state.turn.current_tool_id = Some(id);
+    }
+
+    pub async fn tool_finished(&self) {
+        let mut state = self.state.lock().await;
+        state.turn.current_tool = None;
+        state.turn.current_tool_id = None;
+    }
+
+    pub async fn current_tool_id(&self) -> Option<String> {
+        self.state.lock().await.turn.current_tool_id.clone()
+    }
+
     pub async fn get_state(&self) -> SessionState {
         self.state.lock().await.clone()
     }
@@ -0,0 +1,196 @@
+use crate::error::BridgeError;
+use crate::git::{git, git_env};
+use crate::protocol::ShadowEntry;
+use std::path::{Path, PathBuf};
+
+/// The timeline of turn commits. Never checked out, so the user's branches
+/// and index are left alone.
+pub const SHADOW_REF: &str = "refs/claudecode/shadow";
+
+/// Longest prompt kept in a commit message.
+const PROMPT_EXCERPT: usize = 2000;
+
+const SESSION_TRAILER: &str = "Claudecode-Session";
+const TURN_TRAILER: &str = "Claudecode-Turn";
+const COST_TRAILER: &str = "Claudecode-Cost-Usd";
+
+/// Shadow commits are the bridge's, not the user's.
+const IDENTITY: [(&str, &str); 4] = [
+    ("GIT_AUTHOR_NAME", "claudecode"),
+    ("GIT_AUTHOR_EMAIL", "claudecode@localhost"),
+    ("GIT_COMMITTER_NAME", "claudecode"),
+    ("GIT_COMMITTER_EMAIL", "claudecode@localhost"),
+];
+
+/// Commits the working tree of the repository containing `cwd`, untracked
+/// files included, on top of the shadow ref as turn `turn` of the session,
+/// or the one after its last shadow commit. Uses an index of its own.
+pub async fn commit_turn(
+    cwd: &Path,
+    session_id: &str,
+    turn: Option<u32>,
+    prompt: &str,
+    cost_usd: f64,
+) -> Result<String, BridgeError> {
+    let top = PathBuf::from(git(cwd, &["rev-parse", "--show-toplevel"]).await?);
+    let index = top.join(
+        git(
+            &top,
+            &["rev-parse", "--git-path", "claudecode-shadow-index"],
+        )
+        .await?,
+    );
+    let index = index.display().to_string();
+    let index_env = [("GIT_INDEX_FILE", index.as_str())];
+    git_env(&top, &["add", "-A"], &index_env).await?;
+    let tree = git_env(&top, &["write-tree"], &index_env).await?;
+
+    let parent = shadow_head(&top).await;
+    let turn = match turn {
+        Some(turn) => turn,
+        None => {
+            let earlier = log(&top).await?;
+            earlier
+                .iter()
+                .filter(|e| e.session_id == session_id)
+                .map(|e| e.turn)
+                .max()
+                .unwrap_or(0)
+                + 1
+        }
+    };
+    let message = message(session_id, turn, prompt, cost_usd);
+    let mut args = vec!["commit-tree", tree.as_str(), "-m", message.as_str()];
+    if let Some(ref parent) = parent {
+        args.extend(["-p", parent.as_str()]);
+    }
+    let commit = git_env(&top, &args, &IDENTITY).await?;
+
+    let mut args = vec!["update-ref", SHADOW_REF, commit.as_str()];
+    if let Some(ref parent) = parent {
+        args.push(parent.as_str());
+    }
+    git(&top, &args).await?;
+    Ok(commit)
+}
+
+/// Every shadow commit of the repository containing `cwd`, oldest first.
+pub async fn log(cwd: &Path) -> Result<Vec<ShadowEntry>, BridgeError> {
+    if shadow_head(cwd).await.is_none() {
+        return Ok(Vec::new());
+    }
+    let out = git(cwd, &["log", "--format=%H%x1f%ct%x1f%B%x1e", SHADOW_REF]).await?;
+    let mut entries = parse_log(&out);
+    entries.reverse();
+    Ok(entries)
+}
+
+async fn shadow_head(cwd: &Path) -> Option<String> {
+    let spec = format!("{}^{{commit}}", SHADOW_REF);
+    git(cwd, &["rev-parse", "-q", "--verify", &spec]).await.ok()
+}
+
+fn message(session_id: &str, turn: u32, prompt: &str, cost_usd: f64) -> String {
+    let prompt: String = prompt.trim().chars().take(PROMPT_EXCERPT).collect();
+    format!(
+        "{}\n\n{}: {}\n{}: {}\n{}: {:.6}\n",
+        if prompt.is_empty() {
+            "(no prompt)"
+        } else {
+            &prompt
+        },
+        SESSION_TRAILER,
+        session_id,
+        TURN_TRAILER,
+        turn,
+        COST_TRAILER,
+        cost_usd
+    )
+}
+
+/// Parses `git log` records of hash, commit time and message separated by
+/// 0x1f and terminated by 0x1e. Commits without the trailers are skipped.
+fn parse_log(out: &str) -> Vec<ShadowEntry> {
+    out.split('\x1e')
+        .filter_map(|record| {
+            let mut fields = record.trim_start().splitn(3, '\x1f');
+            let commit = fields.next()?.to_string();
+            let created_at = fields.next()?.parse().ok()?;
+            let body = fields.next()?.trim();
+
+            let (prompt, trailers) = body.rsplit_once("\n\n")?;
+            let trailer = |key: &str| {
+                trailers
+                    .lines()
+                    .find_map(|l| l.strip_prefix(key)?.strip_prefix(": "))
+                    .map(str::to_string)
+            };
+            Some(ShadowEntry {
+                commit,
+                created_at,
+                session_id: trailer(SESSION_TRAILER)?,
+                turn: trailer(TURN_TRAILER)?.parse().ok()?,
+                prompt: prompt.to_string(),
+                cost_usd: trailer(COST_TRAILER)
+                    .and_then(|c| c.parse().ok())
+                    .unwrap_or(0.0),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_reads_trailers() {
+        let record = |hash: &str, msg: &str| format!("{}\x1f1700000000\x1f{}\n\x1e\n", hash, msg);
+        let out = record("abc", &message("s1", 2, "fix the\n\nparser", 0.0123))
+            + &record("def", "someone else's commit");
+        let entries = parse_log(&out);
+        assert_eq!(entries.len(), 1);
+        let e = &entries[0];
+        assert_eq!(e.commit, "abc");
+        assert_eq!(e.created_at, 1700000000);
+        assert_eq!(e.session_id, "s1");
+        assert_eq!(e.turn, 2);
+        assert_eq!(e.prompt, "fix the\n\nparser");
+        assert!((e.cost_usd - 0.0123).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_commit_turn_builds_timeline() {
+        let dir = std::env::temp_dir().join(format!("claudecode-shadow-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "-q"]).await.unwrap();
+        std::fs::write(dir.join("a.txt"), "one").unwrap();
+
+        commit_turn(&dir, "s1", None, "first", 0.5).await.unwrap();
+        std::fs::write(dir.join("a.txt"), "two").unwrap();
+        let second = commit_turn(&dir, "s1", Some(5), "second", 0.25)
+            .await
+            .unwrap();
+
+        let entries = log(&dir).await.unwrap();
+        let turns: Vec<(u32, &str)> = entries
+            .iter()
+            .map(|e| (e.turn, e.prompt.as_str()))
+            .collect();
+        assert_eq!(turns, vec![(1, "first"), (5, "second")]);
+        assert_eq!(entries[1].commit, second);
+        let shown = git(&dir, &["show", &format!("{}:a.txt", second)])
+            .await
+            .unwrap();
+        assert_eq!(shown, "two");
+        // The user's index is untouched.
+        assert_eq!(
+            git(&dir, &["diff", "--cached", "--name-only"])
+                .await
+                .unwrap(),
+            ""
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::claude::unix_now;
+use crate::error::{BridgeError, ErrorCode};
+use crate::protocol::Event;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UsageState {
+    reset_at: Option<u64>,
+}
+
+/// Remembers the last usage-limit window reported by the CLI, across bridge
+/// restarts, so new turns can be refused until the window resets.
+#[derive(Clone)]
+pub struct UsageTracker {
+    state: Arc<Mutex<UsageState>>,
+    path: Option<PathBuf>,
+}
+
+impl UsageTracker {
+    pub fn load() -> Self {
+        Self::load_from(state_path())
+    }
+
+    /// A tracker persisting to `path`, or only in memory.
+    pub fn load_from(path: Option<PathBuf>) -> Self {
+        let state = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            path,
+        }
+    }
+
+    /// The reset time of the current window, if the limit is still in force.
+    pub async fn limited_until(&self) -> Option<u64> {
+        let reset_at = self.state.lock().await.reset_at?;
+        (reset_at > unix_now()).then_some(reset_at)
+    }
+
+    /// Fails with a `rate_limited` error while the usage window is exhausted.
+    pub async fn check(&self) -> Result<(), BridgeError> {
+        let Some(reset_at) = self.limited_until().await else {
+            return Ok(());
+        };
+        let wait = reset_at.saturating_sub(unix_now());
+        let mut err = BridgeError::new(
+            ErrorCode::RateLimited,
+            format!(
+                "Usage limit reached; resets in {}h {:02}m",
+                wait / 3600,
+                (wait % 3600) / 60
+            ),
+        );
+        err.retry_after = Some(wait);
+        Err(err)
+    }
+
+    /// Records a `UsageLimit` event seen on a turn. When the limit was hit
+    /// with a known reset time, persists it and schedules a `limited: false`
+    /// event on `out` for when the window reopens.
+    pub async fn record(
+        &self,
+        reset_at: Option<u64>,
+        limited: bool,
+        out: &mpsc::UnboundedSender<Event>,
+    ) {
+        let Some(reset_at) = reset_at.filter(|_| limited) else {
+            return;
+        };
+        {
+            let mut state = self.state.lock().await;
+            state.reset_at = Some(reset_at);
+            self.persist(&state);
+        }
+        self.schedule_reset(reset_at, out.clone());
+    }
+
+    /// Re-announces a persisted window that is still in force, e.g. on startup.
+    pub async fn announce(&self, out: &mpsc::UnboundedSender<Event>) {
+        if let Some(reset_at) = self.limited_until().await {
+            let _ = out.send(Event::UsageLimit {
+                reset_at: Some(reset_at),
+                limited: true,
+            });
+            self.schedule_reset(reset_at, out.clone());
+        }
+    }
+
+    fn schedule_reset(&self, reset_at: u64, out: mpsc::UnboundedSender<Event>) {
+        let tracker = self.clone();
+        // Held weakly so a pending reset never keeps the output open at exit.
+        let out = out.downgrade();
+        tokio::spawn(async move {
+            let wait = reset_at.saturating_sub(unix_now());
+            tokio::time::sleep(Duration::from_secs(wait)).await;
+            let mut state = tracker.state.lock().await;
+            // A newer window may have been recorded while we slept.
+            if state.reset_at != Some(reset_at) {
+                return;
+            }
+            state.reset_at = None;
+            tracker.persist(&state);
+            let Some(out) = out.upgrade() else {
+                return;
+            };
+            let _ = out.send(Event::UsageLimit {
+                reset_at: Some(reset_at),
+                limited: false,
+            });
+        });
+    }
+
+    fn persist(&self, state: &UsageState) {
+        let Some(ref path) = self.path else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Ok(json) = serde_json::to_string(state) {
+            let _ = std::fs::write(path, json);
+        }
+    }
+}
+
+/// Directory for state the bridge keeps between runs.
+pub fn state_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/state")))?;
+    Some(base.join("claudecode"))
+}
+
+fn state_path() -> Option<PathBuf> {
+    state_dir().map(|d| d.join("usage.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_state(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("claudecode-usage-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("usage.json")
+    }
+
+    #[tokio::test]
+    async fn test_limit_is_enforced_and_reloaded_from_disk() {
+        let path = temp_state("limit");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let tracker = UsageTracker::load_from(Some(path.clone()));
+        assert!(tracker.check().await.is_ok());
+
+        // Without a reset time there is nothing to enforce.
+        tracker.record(None, true, &tx).await;
+        assert!(tracker.check().await.is_ok());
+
+        let reset_at = unix_now() + 3600;
+        tracker.record(Some(reset_at), true, &tx).await;
+        let err = tracker.check().await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::RateLimited);
+        assert!(err.retry_after.is_some_and(|w| w > 3500 && w <= 3600));
+
+        let reloaded = UsageTracker::load_from(Some(path.clone()));
+        assert_eq!(reloaded.limited_until().await, Some(reset_at));
+        assert!(reloaded.check().await.is_err());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_window_resets_and_clears_persisted_state() {
+        let path = temp_state("reset");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let tracker = UsageTracker::load_from(Some(path.clone()));
+        let reset_at = unix_now() + 1;
+        tracker.record(Some(reset_at), true, &tx).await;
+        assert!(tracker.check().await.is_err());
+
+        let event = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match event {
+            Event::UsageLimit {
+                reset_at: at,
+                limited,
+            } => {
+                assert_eq!(at, Some(reset_at));
+                assert!(!limited);
+            }
+            other => panic!("Expected UsageLimit, got {:?}", other),
+        }
+        assert!(tracker.check().await.is_ok());
+        let reloaded = UsageTracker::load_from(Some(path.clone()));
+        assert!(reloaded.limited_until().await.is_none());
+
+        // An expired window on disk is not enforced.
+        std::fs::write(&path, r#"{"reset_at":1}"#).unwrap();
+        assert!(UsageTracker::load_from(Some(path.clone()))
+            .check()
+            .await
+            .is_ok());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
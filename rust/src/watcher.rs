@@ -0,0 +1,428 @@
+use crate::changeset::walk;
+use crate::protocol::{ChangeKind, Event, Severity};
+use crate::session::SessionManager;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use notify::event::{CreateKind, ModifyKind, RemoveKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Repeats of the same change to a path within this window are dropped;
+/// a single write often arrives as several modify events.
+const COALESCE: Duration = Duration::from_millis(200);
+
+/// Directories one turn may watch. Watches are a per-user kernel resource
+/// shared with every other program.
+const MAX_WATCHES: usize = 4096;
+
+/// Reports changes under a turn's working directory as they happen,
+/// attributed to the tool call running at the time. Stops when dropped.
+pub struct TurnWatcher {
+    task: JoinHandle<()>,
+}
+
+impl Drop for TurnWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl TurnWatcher {
+    /// Watches every directory under `root` that isn't ignored, setting up
+    /// in the background. When there are too many, gives up with a warning;
+    /// `summarized` says whether the turn's changeset will cover them.
+    /// `None` when the platform watcher can't be set up.
+    pub fn start(
+        root: PathBuf,
+        session: SessionManager,
+        out: mpsc::UnboundedSender<Event>,
+        summarized: bool,
+    ) -> Option<Self> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                let _ = tx.send(event);
+            }
+        })
+        .ok()?;
+        let watched = Watched {
+            watcher,
+            ignores: Ignores::new(&root),
+            watches: 0,
+            limit: MAX_WATCHES,
+        };
+        let reporter = Reporter {
+            last: HashMap::new(),
+            session,
+            out,
+        };
+        let task = tokio::spawn(run(watched, rx, root, reporter, summarized));
+        Some(Self { task })
+    }
+}
+
+async fn run(
+    watched: Watched,
+    mut rx: mpsc::UnboundedReceiver<notify::Event>,
+    root: PathBuf,
+    mut reporter: Reporter,
+    summarized: bool,
+) {
+    let setup_root = root.clone();
+    let Ok((mut watched, complete)) = tokio::task::spawn_blocking(move || {
+        let mut watched = watched;
+        let complete = watched.add(&setup_root, None);
+        (watched, complete)
+    })
+    .await
+    else {
+        return;
+    };
+    if !complete {
+        return give_up(watched, &root, &reporter.out, summarized);
+    }
+
+    while let Some(event) = rx.recv().await {
+        if let EventKind::Create(CreateKind::Folder) = event.kind {
+            for dir in event.paths {
+                if watched.ignores.is_ignored(&dir, true) {
+                    continue;
+                }
+                // Files may have been created before the watch was.
+                let Ok((back, files, complete)) = tokio::task::spawn_blocking(move || {
+                    let mut files = Vec::new();
+                    let complete = watched.add(&dir, Some(&mut files));
+                    (watched, files, complete)
+                })
+                .await
+                else {
+                    return;
+                };
+                watched = back;
+                for file in files {
+                    reporter.report(file, ChangeKind::Created).await;
+                }
+                if !complete {
+                    return give_up(watched, &root, &reporter.out, summarized);
+                }
+            }
+            continue;
+        }
+        for (path, kind) in changes(event) {
+            if !watched.ignores.is_ignored(&path, false) {
+                reporter.report(path, kind).await;
+            }
+        }
+    }
+}
+
+/// Stops watching a tree too large to watch.
+fn give_up(watched: Watched, root: &Path, out: &mpsc::UnboundedSender<Event>, summarized: bool) {
+    drop(watched);
+    let then = if summarized {
+        "its changes are reported when the turn ends"
+    } else {
+        "set track_changes for a summary when the turn ends"
+    };
+    let _ = out.send(Event::Log {
+        severity: Severity::Warning,
+        message: format!(
+            "{} has too many directories to watch for changes; {}",
+            root.display(),
+            then
+        ),
+    });
+}
+
+/// Emits `file_changed` events, dropping quick repeats.
+struct Reporter {
+    last: HashMap<PathBuf, (ChangeKind, Instant)>,
+    session: SessionManager,
+    out: mpsc::UnboundedSender<Event>,
+}
+
+impl Reporter {
+    async fn report(&mut self, path: PathBuf, kind: ChangeKind) {
+        let now = Instant::now();
+        if let Some((prev, at)) = self.last.get(&path) {
+            if *prev == kind && now.duration_since(*at) < COALESCE {
+                return;
+            }
+        }
+        self.last.insert(path.clone(), (kind, now));
+        let _ = self.out.send(Event::FileChanged {
+            path: path.display().to_string(),
+            kind,
+            by_tool_id: self.session.current_tool_id().await,
+        });
+    }
+}
+
+/// The platform watcher and what it covers.
+struct Watched {
+    watcher: RecommendedWatcher,
+    ignores: Ignores,
+    watches: usize,
+    limit: usize,
+}
+
+impl Watched {
+    /// Watches `dir` and every directory under it that isn't ignored,
+    /// collecting the files found into `files`. Blocking. False once the
+    /// watch limit is reached.
+    fn add(&mut self, dir: &Path, mut files: Option<&mut Vec<PathBuf>>) -> bool {
+        for entry in walk(dir).flatten() {
+            let Some(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if self.watches >= self.limit {
+                    return false;
+                }
+                match self
+                    .watcher
+                    .watch(entry.path(), RecursiveMode::NonRecursive)
+                {
+                    Ok(()) => self.watches += 1,
+                    Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+                        self.watches = self.limit;
+                        return false;
+                    }
+                    Err(_) => {}
+                }
+            } else {
+                if entry.file_name() == ".gitignore" {
+                    self.ignores.add(Gitignore::new(entry.path()).0);
+                }
+                if let Some(ref mut files) = files {
+                    files.push(entry.into_path());
+                }
+            }
+        }
+        true
+    }
+}
+
+/// The changes a watcher event stands for. Directory events and
+/// metadata-only changes are left out.
+fn changes(event: notify::Event) -> Vec<(PathBuf, ChangeKind)> {
+    let kind = match event.kind {
+        EventKind::Create(CreateKind::Folder) | EventKind::Remove(RemoveKind::Folder) => {
+            return Vec::new()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            let mut paths = event.paths.into_iter();
+            return match (paths.next(), paths.next()) {
+                (Some(from), Some(to)) => {
+                    vec![(from, ChangeKind::Deleted), (to, ChangeKind::Created)]
+                }
+                _ => Vec::new(),
+            };
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => ChangeKind::Deleted,
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => ChangeKind::Created,
+        EventKind::Modify(ModifyKind::Metadata(_)) => return Vec::new(),
+        EventKind::Create(_) => ChangeKind::Created,
+        EventKind::Modify(_) => ChangeKind::Modified,
+        EventKind::Remove(_) => ChangeKind::Deleted,
+        EventKind::Access(_) | EventKind::Any | EventKind::Other => return Vec::new(),
+    };
+    event.paths.into_iter().map(|p| (p, kind)).collect()
+}
+
+/// The ignore rules git applies to the watched tree: the same sources the
+/// walker reads.
+pub struct Ignores {
+    /// .gitignore files in the tree and above it in the repository,
+    /// deepest first.
+    nested: Vec<Gitignore>,
+    /// .git/info/exclude, then core.excludesFile; both rank below any
+    /// .gitignore.
+    repo: Vec<Gitignore>,
+}
+
+impl Ignores {
+    /// Rules from outside `root`; the walk adds the .gitignore files in it.
+    pub fn new(root: &Path) -> Self {
+        Self::within(root, root)
+    }
+
+    /// Rules from above `path`, up to the repository top or, outside a
+    /// repository, up to `project`.
+    pub fn within(path: &Path, project: &Path) -> Self {
+        let top = path.ancestors().find(|dir| dir.join(".git").exists());
+        let root = top.unwrap_or(project);
+        let mut ignores = Self {
+            nested: Vec::new(),
+            repo: Vec::new(),
+        };
+        for dir in path.ancestors().skip(1).take_while(|d| d.starts_with(root)) {
+            let file = dir.join(".gitignore");
+            if file.is_file() {
+                ignores.add(Gitignore::new(file).0);
+            }
+        }
+        if let Some(git_dir) = top.map(common_git_dir) {
+            let mut builder = GitignoreBuilder::new(root);
+            builder.add(git_dir.join("info").join("exclude"));
+            if let Ok(exclude) = builder.build() {
+                ignores.repo.push(exclude);
+            }
+        }
+        ignores
+            .repo
+            .push(GitignoreBuilder::new(root).build_global().0);
+        ignores
+    }
+
+    fn add(&mut self, gitignore: Gitignore) {
+        self.nested.push(gitignore);
+        self.nested
+            .sort_by_key(|gi| Reverse(gi.path().components().count()));
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if path.components().any(|c| c.as_os_str() == ".git") {
+            return true;
+        }
+        for gi in self.nested.iter().chain(&self.repo) {
+            if !path.starts_with(gi.path()) {
+                continue;
+            }
+            match gi.matched_path_or_any_parents(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// The git directory shared by all of `top`'s worktrees, which holds
+/// info/exclude.
+fn common_git_dir(top: &Path) -> PathBuf {
+    let dot_git = top.join(".git");
+    if dot_git.is_dir() {
+        return dot_git;
+    }
+    // A linked worktree: `.git` is a file naming its own git directory,
+    // which in turn names the common one.
+    let Some(git_dir) = std::fs::read_to_string(&dot_git)
+        .ok()
+        .and_then(|s| s.strip_prefix("gitdir:").map(|p| top.join(p.trim())))
+    else {
+        return dot_git;
+    };
+    match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => git_dir.join(common.trim()),
+        Err(_) => git_dir,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Waits until the watcher reports changes under `root`.
+    async fn until_watching(root: &Path, rx: &mut mpsc::UnboundedReceiver<Event>) {
+        let probe = root.join("probe");
+        for _ in 0..50 {
+            std::fs::write(&probe, "").unwrap();
+            if let Ok(Some(_)) = tokio::time::timeout(Duration::from_millis(100), rx.recv()).await {
+                break;
+            }
+        }
+        std::fs::remove_file(&probe).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        while rx.try_recv().is_ok() {}
+    }
+
+    #[tokio::test]
+    async fn test_watcher_attributes_changes_to_running_tool() {
+        let root = std::env::temp_dir().join(format!("claudecode-watcher-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+
+        let session = SessionManager::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let _watcher = TurnWatcher::start(root.clone(), session.clone(), tx, true).unwrap();
+        until_watching(&root, &mut rx).await;
+        session
+            .tool_started("Bash".to_string(), "t1".to_string())
+            .await;
+        std::fs::write(root.join("target/out.o"), "ignored").unwrap();
+        std::fs::write(root.join("a.txt"), "hi").unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match event {
+            Event::FileChanged {
+                path,
+                kind,
+                by_tool_id,
+            } => {
+                assert_eq!(path, root.join("a.txt").display().to_string());
+                assert_eq!(kind, ChangeKind::Created);
+                assert_eq!(by_tool_id.as_deref(), Some("t1"));
+            }
+            other => panic!("Expected FileChanged, got {:?}", other),
+        }
+
+        // A file written into a new directory before its watch exists.
+        std::fs::create_dir_all(root.join("new/deeper")).unwrap();
+        std::fs::write(root.join("new/deeper/b.txt"), "hi").unwrap();
+        let nested = root.join("new/deeper/b.txt").display().to_string();
+        let found = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(event) = rx.recv().await {
+                if matches!(event, Event::FileChanged { ref path, .. } if *path == nested) {
+                    return;
+                }
+            }
+        })
+        .await;
+        assert!(found.is_ok(), "no event for {}", nested);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_ignores_read_info_exclude_and_parent_gitignore() {
+        let repo = std::env::temp_dir().join(format!("claudecode-ignores-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&repo);
+        std::fs::create_dir_all(repo.join(".git/info")).unwrap();
+        std::fs::create_dir_all(repo.join("sub")).unwrap();
+        std::fs::write(repo.join(".git/info/exclude"), "*.local\n").unwrap();
+        std::fs::write(repo.join(".gitignore"), "*.log\n").unwrap();
+
+        let ignores = Ignores::new(&repo.join("sub"));
+        assert!(ignores.is_ignored(&repo.join("sub/a.local"), false));
+        assert!(ignores.is_ignored(&repo.join("sub/a.log"), false));
+        assert!(!ignores.is_ignored(&repo.join("sub/a.rs"), false));
+        let _ = std::fs::remove_dir_all(&repo);
+    }
+
+    #[tokio::test]
+    async fn test_watch_limit_stops_adding() {
+        let root =
+            std::env::temp_dir().join(format!("claudecode-watch-limit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+        let mut watched = Watched {
+            watcher: notify::recommended_watcher(|_: notify::Result<notify::Event>| {}).unwrap(),
+            ignores: Ignores::new(&root),
+            watches: 0,
+            limit: 2,
+        };
+        assert!(!watched.add(&root, None));
+        assert_eq!(watched.watches, 2);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
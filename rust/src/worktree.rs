@@ -0,0 +1,260 @@
+use crate::claude::unix_now;
+use crate::error::{BridgeError, ErrorCode};
+use crate::git::git;
+use crate::usage::state_dir;
+use std::path::{Path, PathBuf};
+
+/// A git worktree on its own branch that isolated turns run in, away from
+/// the user's working copy.
+#[derive(Debug, Clone)]
+pub struct Worktree {
+    /// Top level of the repository the worktree was made from.
+    pub repo: PathBuf,
+    pub path: PathBuf,
+    pub branch: String,
+}
+
+impl Worktree {
+    /// Adds a worktree on a new branch from the HEAD of the repository
+    /// containing `cwd`. Uncommitted changes in the working copy stay there.
+    pub async fn create(cwd: &Path) -> Result<Self, BridgeError> {
+        let dir = state_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("worktrees");
+        Self::create_in(cwd, &dir).await
+    }
+
+    /// Like `create`, with the worktree under `dir`.
+    pub async fn create_in(cwd: &Path, dir: &Path) -> Result<Self, BridgeError> {
+        let repo = PathBuf::from(git(cwd, &["rev-parse", "--show-toplevel"]).await?);
+        // Keep the name usable in a branch and a directory.
+        let name: String = repo
+            .file_name()
+            .map_or("repo".into(), |n| n.to_string_lossy().into_owned())
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        let stamp = unix_now();
+        let branch = format!("claudecode/{}-{}", name, stamp);
+        let path = dir.join(format!("{}-{}", name, stamp));
+        let path_arg = path.display().to_string();
+        git(
+            &repo,
+            &["worktree", "add", "-b", &branch, &path_arg, "HEAD"],
+        )
+        .await?;
+        Ok(Self { repo, path, branch })
+    }
+
+    /// Worktrees on `claudecode/` branches of the repository containing
+    /// `cwd`, oldest first: ones a bridge that went away never closed.
+    pub async fn leftover(cwd: &Path) -> Result<Vec<Self>, BridgeError> {
+        let list = git(cwd, &["worktree", "list", "--porcelain"]).await?;
+        let mut repo = None;
+        let mut found = Vec::new();
+        for entry in list.split("\n\n") {
+            let mut path = None;
+            let mut branch = None;
+            for line in entry.lines() {
+                if let Some(p) = line.strip_prefix("worktree ") {
+                    path = Some(PathBuf::from(p));
+                } else if let Some(b) = line.strip_prefix("branch refs/heads/") {
+                    branch = Some(b.to_string());
+                }
+            }
+            let Some(path) = path else { continue };
+            // The main working tree always comes first.
+            let Some(repo) = repo.as_ref() else {
+                repo = Some(path);
+                continue;
+            };
+            if let Some(branch) = branch.filter(|b| b.starts_with("claudecode/")) {
+                found.push(Self {
+                    repo: repo.clone(),
+                    path,
+                    branch,
+                });
+            }
+        }
+        found.sort_by(|a, b| a.branch.cmp(&b.branch));
+        Ok(found)
+    }
+
+    /// Where a turn asked to run in `cwd` runs instead: the same directory
+    /// within the worktree.
+    pub fn cwd_for(&self, cwd: &Path) -> PathBuf {
+        if cwd.starts_with(&self.path) {
+            return cwd.to_path_buf();
+        }
+        match cwd.strip_prefix(&self.repo) {
+            Ok(rel) => self.path.join(rel),
+            Err(_) => self.path.clone(),
+        }
+    }
+
+    /// Commits whatever the turns left in the worktree and merges the
+    /// branch into the repository's current branch. Refused while the
+    /// working copy has uncommitted changes to tracked files, which the
+    /// merge could tangle with the turns' changes. A failed merge is
+    /// aborted and the worktree kept.
+    pub async fn merge(&self, message: &str) -> Result<(), BridgeError> {
+        let dirty = git(
+            &self.repo,
+            &["status", "--porcelain", "--untracked-files=no"],
+        )
+        .await?;
+        if !dirty.is_empty() {
+            return Err(BridgeError::new(
+                ErrorCode::GitFailed,
+                format!(
+                    "{} has uncommitted changes; commit or stash them before merging {}",
+                    self.repo.display(),
+                    self.branch
+                ),
+            ));
+        }
+        git(&self.path, &["add", "-A"]).await?;
+        if !git(&self.path, &["status", "--porcelain"])
+            .await?
+            .is_empty()
+        {
+            git(&self.path, &["commit", "-q", "-m", message]).await?;
+        }
+        if let Err(e) = git(&self.repo, &["merge", "--no-edit", &self.branch]).await {
+            let _ = git(&self.repo, &["merge", "--abort"]).await;
+            return Err(BridgeError::new(
+                ErrorCode::GitFailed,
+                format!(
+                    "Merging {} failed; the worktree is kept at {}: {}",
+                    self.branch,
+                    self.path.display(),
+                    e.message
+                ),
+            ));
+        }
+        self.remove("-d").await
+    }
+
+    /// Removes the worktree and its branch, dropping any changes in it.
+    pub async fn discard(&self) -> Result<(), BridgeError> {
+        self.remove("-D").await
+    }
+
+    async fn remove(&self, delete_flag: &str) -> Result<(), BridgeError> {
+        let path_arg = self.path.display().to_string();
+        git(&self.repo, &["worktree", "remove", "--force", &path_arg]).await?;
+        git(&self.repo, &["branch", delete_flag, &self.branch]).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cwd_for_maps_into_worktree() {
+        let wt = Worktree {
+            repo: PathBuf::from("/src/app"),
+            path: PathBuf::from("/state/worktrees/app-1"),
+            branch: "claudecode/app-1".to_string(),
+        };
+        assert_eq!(
+            wt.cwd_for(Path::new("/src/app/lib")),
+            PathBuf::from("/state/worktrees/app-1/lib")
+        );
+        assert_eq!(
+            wt.cwd_for(Path::new("/state/worktrees/app-1/lib")),
+            PathBuf::from("/state/worktrees/app-1/lib")
+        );
+        assert_eq!(
+            wt.cwd_for(Path::new("/elsewhere")),
+            PathBuf::from("/state/worktrees/app-1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_merge_and_discard() {
+        let dir = std::env::temp_dir().join(format!("claudecode-worktree-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = dir.join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q"]).await.unwrap();
+        git(&repo, &["config", "user.name", "t"]).await.unwrap();
+        git(&repo, &["config", "user.email", "t@t"]).await.unwrap();
+        std::fs::write(repo.join("a.txt"), "one\n").unwrap();
+        git(&repo, &["add", "a.txt"]).await.unwrap();
+        git(&repo, &["commit", "-q", "-m", "add a"]).await.unwrap();
+        let worktrees = dir.join("worktrees");
+        let branches = || async {
+            git(&repo, &["branch", "--list", "claudecode/*"])
+                .await
+                .unwrap()
+        };
+
+        // Discarding drops the worktree, its branch and its changes.
+        let wt = Worktree::create_in(&repo, &worktrees).await.unwrap();
+        let left = Worktree::leftover(&repo).await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].repo, wt.repo);
+        assert_eq!(left[0].path, wt.path);
+        assert_eq!(left[0].branch, wt.branch);
+        assert_eq!(
+            std::fs::read_to_string(wt.path.join("a.txt")).unwrap(),
+            "one\n"
+        );
+        std::fs::write(wt.path.join("a.txt"), "dropped\n").unwrap();
+        wt.discard().await.unwrap();
+        assert!(!wt.path.exists());
+        assert_eq!(branches().await, "");
+        assert_eq!(
+            std::fs::read_to_string(repo.join("a.txt")).unwrap(),
+            "one\n"
+        );
+
+        let wt = Worktree::create_in(&repo, &worktrees).await.unwrap();
+        std::fs::write(wt.path.join("a.txt"), "two\n").unwrap();
+        std::fs::write(wt.path.join("b.txt"), "new\n").unwrap();
+
+        // A dirty working copy is refused and nothing is lost.
+        std::fs::write(repo.join("a.txt"), "local\n").unwrap();
+        let err = wt.merge("turns").await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::GitFailed);
+        assert!(
+            err.message.contains("uncommitted changes"),
+            "{}",
+            err.message
+        );
+        assert!(wt.path.exists());
+        assert_eq!(
+            std::fs::read_to_string(wt.path.join("a.txt")).unwrap(),
+            "two\n"
+        );
+
+        // Once clean, the turns' changes land on the current branch.
+        git(&repo, &["checkout", "--", "a.txt"]).await.unwrap();
+        wt.merge("turns").await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.join("a.txt")).unwrap(),
+            "two\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.join("b.txt")).unwrap(),
+            "new\n"
+        );
+        assert_eq!(
+            git(&repo, &["log", "-1", "--format=%s"]).await.unwrap(),
+            "turns"
+        );
+        assert!(!wt.path.exists());
+        assert_eq!(branches().await, "");
+        assert!(Worktree::leftover(&repo).await.unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,133 @@
+//! The bridge must not leave a `claude` process, or anything it started,
+//! behind when Neovim goes away, whether it closes stdin or the bridge is
+//! signalled.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const FAKE_CLAUDE: &str = r#"#!/bin/sh
+sleep 60 &
+echo $$ $! > "$FAKE_CLAUDE_PIDFILE"
+echo '{"type":"system","subtype":"init","session_id":"s1","model":"fake"}'
+exec sleep 60
+"#;
+
+fn fake_claude_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("claudecode-bridge-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("claude");
+    std::fs::write(&script, FAKE_CLAUDE).unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    dir
+}
+
+fn spawn_bridge(dir: &Path) -> Child {
+    let path = format!(
+        "{}:{}",
+        dir.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    Command::new(env!("CARGO_BIN_EXE_claudecode-bridge"))
+        .env("PATH", path)
+        .env("FAKE_CLAUDE_PIDFILE", dir.join("pid"))
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+/// Starts a turn and returns the pids of the fake CLI and the tool it
+/// started, once the bridge reports the session.
+fn start_turn(bridge: &mut Child, dir: &Path) -> (Vec<i32>, BufReader<ChildStdout>) {
+    let stdin = bridge.stdin.as_mut().unwrap();
+    writeln!(stdin, r#"{{"method":"chat","params":{{"prompt":"hi"}}}}"#).unwrap();
+    stdin.flush().unwrap();
+
+    let mut stdout = BufReader::new(bridge.stdout.take().unwrap());
+    let mut line = String::new();
+    loop {
+        line.clear();
+        assert!(
+            stdout.read_line(&mut line).unwrap() > 0,
+            "bridge closed stdout"
+        );
+        if line.contains(r#""event":"init""#) {
+            break;
+        }
+    }
+    let pids = std::fs::read_to_string(dir.join("pid")).unwrap();
+    let pids = pids
+        .split_whitespace()
+        .map(|p| p.parse().unwrap())
+        .collect();
+    (pids, stdout)
+}
+
+fn wait_exit(bridge: &mut Child) -> Option<i32> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(status) = bridge.try_wait().unwrap() {
+            return status.code();
+        }
+        assert!(Instant::now() < deadline, "bridge did not exit");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Whether `pid` is gone within a moment; processes killed along with the
+/// group are reaped by init, not by the bridge.
+fn exits_soon(pid: i32) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while alive(pid) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    true
+}
+
+fn alive(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the pid exists.
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+#[test]
+fn stdin_eof_terminates_claude() {
+    let dir = fake_claude_dir("eof");
+    let mut bridge = spawn_bridge(&dir);
+    let (pids, _stdout) = start_turn(&mut bridge, &dir);
+    assert!(pids.iter().all(|&pid| alive(pid)));
+
+    drop(bridge.stdin.take());
+    assert_eq!(wait_exit(&mut bridge), Some(0));
+    for pid in pids {
+        assert!(exits_soon(pid), "process {} was orphaned", pid);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn sigterm_terminates_claude() {
+    let dir = fake_claude_dir("sigterm");
+    let mut bridge = spawn_bridge(&dir);
+    let (pids, _stdout) = start_turn(&mut bridge, &dir);
+    assert!(pids.iter().all(|&pid| alive(pid)));
+
+    // SAFETY: plain signal delivery to our own child.
+    unsafe {
+        libc::kill(bridge.id() as libc::pid_t, libc::SIGTERM);
+    }
+    assert_eq!(wait_exit(&mut bridge), Some(128 + libc::SIGTERM));
+    for pid in pids {
+        assert!(exits_soon(pid), "process {} was orphaned", pid);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}